@@ -0,0 +1,144 @@
+use crate::error::RollError;
+use crate::interpreter::{Op, Value};
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+/// The most bits a result may have, so something like `3 ** 4000000000` gives an error
+/// instead of using up all memory.
+const MAX_BITS: u64 = 1 << 20;
+
+impl Value {
+    /// A `Big`, or an `Int` when it fits in one.
+    pub(crate) fn big(v: BigInt) -> Self {
+        match v.to_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Big(v),
+        }
+    }
+
+    pub(crate) fn parse_int(s: &str) -> Option<Value> {
+        s.parse().ok().map(Value::big)
+    }
+
+    pub(crate) fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Value::Int(i) => Some(BigInt::from(*i)),
+            Value::Big(b) => Some(b.clone()),
+            Value::Float(_) | Value::Bool(_) => None,
+        }
+    }
+
+    /// Applies `op` to two integers, at least one of which is a `Big` or doesn't fit in an
+    /// `Int` after `op`. When either is a float, the result is a float like it is for `Int`s.
+    pub(crate) fn big_op(self, rhs: Value, op: Op) -> Result<Value, RollError> {
+        let (l, r) = match (self.to_big(), rhs.to_big()) {
+            (Some(l), Some(r)) => (l, r),
+            _ => {
+                let (l, r) = (f64::from(self), f64::from(rhs));
+                return Ok(Value::Float(match op {
+                    Op::Add => l + r,
+                    Op::Sub => l - r,
+                    Op::Mul => l * r,
+                    Op::Rem => l % r,
+                    Op::IDiv => (l / r).floor(),
+                    Op::Pow => l.powf(r),
+                }));
+            }
+        };
+
+        Ok(match op {
+            Op::Add => Value::big(l + r),
+            Op::Sub => Value::big(l - r),
+            Op::Mul if l.bits() + r.bits() > MAX_BITS => return Err(RollError::Overflow),
+            Op::Mul => Value::big(l * r),
+            Op::Rem if r.is_zero() => Value::Float(f64::NAN),
+            Op::Rem => Value::big(l % r),
+            Op::IDiv if r.is_zero() => Value::Float(f64::from(Value::big(l)) / 0.0),
+            Op::IDiv => Value::big(l.div_floor(&r)),
+            Op::Pow if r.is_negative() => {
+                Value::Float(f64::from(Value::big(l)).powf(r.to_f64().unwrap()))
+            }
+            Op::Pow => {
+                // Only 0, 1 and -1 have powers this large that fit, and for those only
+                // whether the power is odd matters
+                let e = match r.to_u32() {
+                    Some(e) => e,
+                    None if l.magnitude() <= &1u32.into() => (&r % 2u32).to_u32().unwrap() + 2,
+                    None => return Err(RollError::Overflow),
+                };
+                if l.bits().saturating_mul(e as u64) > MAX_BITS {
+                    return Err(RollError::Overflow);
+                }
+                Value::big(l.pow(e))
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::RollError;
+    use crate::interpreter::Value;
+    use crate::parser::Parser;
+    use crate::roll::tests::DeterministicRng;
+
+    fn interp(s: &str) -> Result<Value, RollError> {
+        let mut p = Parser::new(s);
+        p.advanced = true;
+        p.parse()
+            .unwrap()
+            .interp_with_rng(&mut Vec::new(), &mut DeterministicRng::new())
+    }
+
+    fn big(s: &str) -> String {
+        interp(s).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_widen() {
+        assert_eq!(big("9223372036854775807 + 1"), "9223372036854775808");
+        assert_eq!(big("-(-9223372036854775807 - 1)"), "9223372036854775808");
+        assert_eq!(big("10 ** 30"), format!("1{}", "0".repeat(30)));
+        assert_eq!(big("99999999999999999999 * 2"), "199999999999999999998");
+        assert_eq!(big("(2 ** 64) mod 10"), "6");
+        assert_eq!(big("(2 ** 64) / 2"), "9223372036854776000");
+        assert_eq!(big("(2 ** 64) + 0.5"), "18446744073709552000");
+    }
+
+    #[test]
+    fn test_narrow() {
+        // Results that fit in an i64 again are normal integers
+        assert_eq!(interp("(2 ** 64) - (2 ** 64) + 1"), Ok(Value::Int(1)));
+        assert_eq!(interp("(2 ** 70) // (2 ** 60)"), Ok(Value::Int(1024)));
+        assert!(matches!(interp("(10 ** 30) // 1"), Ok(Value::Big(_))));
+    }
+
+    #[test]
+    fn test_big_idiv() {
+        // Big integers are divided exactly, not as floats
+        assert_eq!(big("(10 ** 30) // 1"), format!("1{}", "0".repeat(30)));
+        assert_eq!(big("(10 ** 30) // 7"), "142857142857142857142857142857");
+        assert_eq!(big("-(10 ** 30) // 7"), "-142857142857142857142857142858");
+        assert_eq!(big("7 // (10 ** 30)"), "0");
+        assert_eq!(big("-7 // (10 ** 30)"), "-1");
+        assert_eq!(
+            big("(-9223372036854775807 - 1) // -1"),
+            "9223372036854775808"
+        );
+        assert_eq!(big("(10 ** 30) // 0"), "inf");
+    }
+
+    #[test]
+    fn test_big_rolls() {
+        // DeterministicRng rolls the lowest faces first, so the dice add up to 1 + 2 + ... + 100
+        assert_eq!(
+            big("(100d1000000 * 1000000) ** 3"),
+            5_050_000_000u128.pow(3).to_string()
+        );
+
+        assert_eq!(interp("(2 ** 64)d6"), Err(RollError::Overflow));
+        assert_eq!(interp("3 ** 4000000000"), Err(RollError::Overflow));
+        assert_eq!(interp("(0 - 1) ** (2 ** 70 + 1)"), Ok(Value::Int(-1)));
+    }
+}
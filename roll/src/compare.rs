@@ -0,0 +1,115 @@
+use crate::error::RollError;
+use crate::interpreter::Ast;
+use crate::limits::Limits;
+use crate::simulation::{Analysis, Method};
+use crate::span::Span;
+use rand_core::RngCore;
+
+/// How two independent rolls compare to each other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matchup {
+    /// The distribution of the first roll minus the second.
+    pub difference: Analysis,
+}
+
+impl Matchup {
+    /// The chance the first roll is higher.
+    pub fn greater(&self) -> f64 {
+        let dist = self.difference.distribution();
+        dist.outcomes()
+            .iter()
+            .filter(|(v, _)| *v > 0.0)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// The chance both rolls are the same.
+    pub fn equal(&self) -> f64 {
+        self.difference.distribution().probability(0.0)
+    }
+
+    /// The chance the second roll is higher.
+    pub fn less(&self) -> f64 {
+        let dist = self.difference.distribution();
+        dist.outcomes()
+            .iter()
+            .filter(|(v, _)| *v < 0.0)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// How much higher the first roll is on average.
+    pub fn mean_difference(&self) -> f64 {
+        self.difference.distribution().mean()
+    }
+}
+
+impl Ast {
+    /// Compares this expression to another one, either exactly or by simulating both within
+    /// `limits`.
+    pub fn compare(
+        &self,
+        other: &Ast,
+        method: Method,
+        rng: &mut dyn RngCore,
+        limits: &Limits,
+    ) -> Result<Matchup, RollError> {
+        let difference = Ast::Sub(
+            Box::new(self.clone()),
+            Box::new(other.clone()),
+            Span::default(),
+        );
+
+        Ok(Matchup {
+            difference: difference.analyze(method, rng, limits)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::limits::Limits;
+    use crate::parser::Parser;
+    use crate::roll::tests::SeededRng;
+    use crate::simulation::{Analysis, Method};
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn compare(a: &str, b: &str, method: Method) -> super::Matchup {
+        let a = Parser::new(a).parse().unwrap();
+        let b = Parser::new(b).parse().unwrap();
+        a.compare(&b, method, &mut SeededRng { state: 5 }, &Limits::DEFAULT)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_compare() {
+        let m = compare("2d6", "1d12", Method::Auto);
+        assert!(matches!(m.difference, Analysis::Exact(_)));
+        assert!(close(m.mean_difference(), 0.5));
+        assert!(close(m.greater() + m.equal() + m.less(), 1.0));
+        // Whatever 2d6 rolls, the d12 has a 1 in 12 chance of rolling the same
+        assert!(close(m.equal(), 1.0 / 12.0));
+
+        let m = compare("d20+7", "d20+4", Method::Auto);
+        assert!(close(m.mean_difference(), 3.0));
+        assert!(close(m.greater(), 247.0 / 400.0));
+        assert!(close(m.equal(), 17.0 / 400.0));
+        assert!(close(m.less(), 136.0 / 400.0));
+        assert_eq!(m.difference.distribution().min(), -16.0);
+        assert_eq!(m.difference.distribution().max(), 22.0);
+    }
+
+    #[test]
+    fn test_compare_simulated() {
+        let m = compare("3d6!kh2", "2d6", Method::Auto);
+        assert!(matches!(m.difference, Analysis::Simulated(_)));
+        assert!(close(m.greater() + m.equal() + m.less(), 1.0));
+
+        // The same as the exact result, within a few standard errors
+        let sim = compare("2d6", "1d12", Method::Simulate(20_000));
+        assert!((sim.mean_difference() - 0.5).abs() < 0.1);
+    }
+}
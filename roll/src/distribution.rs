@@ -0,0 +1,828 @@
+use crate::error::RollError;
+use crate::filtermodifier::{
+    Comparison, ExplodeModifier, FilterModifier, RerollModifier, SuccessModifier, Target,
+};
+use crate::functions::Function;
+use crate::interpreter::{Ast, Value, DEFAULT_SIDES};
+use crate::limits::Limits;
+use crate::sides::Sides;
+use std::collections::HashMap;
+
+/// The maximum number of steps a single calculation may take, so that something
+/// like `1000000d1000` gives an error instead of running for hours.
+const MAX_WORK: u128 = 10_000_000;
+
+/// Explosions are followed until the chance of getting this far is smaller than this.
+/// Following them any further wouldn't change the result within floating point precision.
+const NEGLIGIBLE: f64 = 1e-18;
+
+/// The exact probability distribution of a roll: every total it can have, together
+/// with the chance of getting it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distribution {
+    /// Sorted by value, every value appears once.
+    outcomes: Vec<(f64, f64)>,
+}
+
+impl Distribution {
+    /// A distribution that always has the same value.
+    pub fn constant(v: f64) -> Self {
+        Self {
+            outcomes: vec![(v, 1.0)],
+        }
+    }
+
+    fn empty() -> Self {
+        Self {
+            outcomes: Vec::new(),
+        }
+    }
+
+    /// Builds a distribution from values and chances, adding up the chances of equal values.
+    pub(crate) fn from_outcomes(outcomes: impl IntoIterator<Item = (f64, f64)>) -> Self {
+        let mut merged: HashMap<u64, f64> = HashMap::new();
+        for (v, p) in outcomes {
+            // Adding 0.0 turns -0.0 into 0.0, so both end up as the same value
+            *merged.entry((v + 0.0).to_bits()).or_insert(0.0) += p;
+        }
+
+        let mut outcomes: Vec<_> = merged
+            .into_iter()
+            .filter(|(_, p)| *p > 0.0)
+            .map(|(v, p)| (f64::from_bits(v), p))
+            .collect();
+        outcomes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { outcomes }
+    }
+
+    /// Every value this distribution can have together with its chance, sorted by value.
+    pub fn outcomes(&self) -> &[(f64, f64)] {
+        &self.outcomes
+    }
+
+    /// The lowest possible value.
+    pub fn min(&self) -> f64 {
+        self.outcomes[0].0
+    }
+
+    /// The highest possible value.
+    pub fn max(&self) -> f64 {
+        self.outcomes[self.outcomes.len() - 1].0
+    }
+
+    /// The average value.
+    pub fn mean(&self) -> f64 {
+        self.outcomes.iter().map(|(v, p)| v * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.outcomes
+            .iter()
+            .map(|(v, p)| (v - mean).powi(2) * p)
+            .sum()
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// The chance of getting exactly `k`.
+    pub fn probability(&self, k: f64) -> f64 {
+        self.outcomes
+            .iter()
+            .filter(|(v, _)| *v == k)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// The chance of getting `k` or more.
+    pub fn at_least(&self, k: f64) -> f64 {
+        self.outcomes
+            .iter()
+            .filter(|(v, _)| *v >= k)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    /// The chance of getting `k` or less.
+    pub fn at_most(&self, k: f64) -> f64 {
+        self.outcomes
+            .iter()
+            .filter(|(v, _)| *v <= k)
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    fn total_probability(&self) -> f64 {
+        self.outcomes.iter().map(|(_, p)| p).sum()
+    }
+
+    fn map(&self, f: impl Fn(f64) -> f64) -> Self {
+        Self::from_outcomes(self.outcomes.iter().map(|(v, p)| (f(*v), *p)))
+    }
+
+    fn scale(&self, factor: f64) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.outcomes.iter().map(move |(v, p)| (*v, p * factor))
+    }
+
+    fn scale_by(&self, factor: impl Fn(f64) -> f64) -> Self {
+        Self::from_outcomes(self.outcomes.iter().map(|(v, p)| (*v, p * factor(*v))))
+    }
+
+    /// Splits the outcomes into the ones matching the target and the ones that don't.
+    fn split(&self, target: Target<i64>) -> (Self, Self) {
+        let (hit, miss) = self
+            .outcomes
+            .iter()
+            .partition(|(v, _)| target.matches(*v as i64));
+        (Self { outcomes: hit }, Self { outcomes: miss })
+    }
+
+    /// Adds the outcomes of another (partial) distribution to this one.
+    fn mix(self, other: Self) -> Self {
+        Self::from_outcomes(self.outcomes.into_iter().chain(other.outcomes))
+    }
+
+    /// The distribution of `f(x, y)`, where `x` and `y` are taken from two independent distributions.
+    fn combine(&self, other: &Self, f: impl Fn(f64, f64) -> f64) -> Result<Self, RollError> {
+        check_work(self.outcomes.len() as u128 * other.outcomes.len() as u128)?;
+
+        let f = &f;
+        Ok(Self::from_outcomes(self.outcomes.iter().flat_map(
+            |&(x, p)| other.outcomes.iter().map(move |&(y, q)| (f(x, y), p * q)),
+        )))
+    }
+
+    fn add(&self, other: &Self) -> Result<Self, RollError> {
+        self.combine(other, |x, y| x + y)
+    }
+
+    /// The distribution of the sum of `n` independent values from this distribution.
+    fn repeat(&self, mut n: u64) -> Result<Self, RollError> {
+        // The last step adds two halves of the sum, which both have about this many outcomes
+        check_work((n as u128 * self.outcomes.len() as u128 / 2).saturating_pow(2))?;
+
+        let mut res = Self::constant(0.0);
+        let mut power = self.clone();
+        while n > 0 {
+            if n & 1 == 1 {
+                res = res.add(&power)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                power = power.add(&power)?;
+            }
+        }
+        Ok(res)
+    }
+}
+
+fn check_work(work: u128) -> Result<(), RollError> {
+    if work > MAX_WORK {
+        Err(RollError::LimitExceeded(
+            "outcomes to calculate the exact distribution".to_string(),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn to_int(v: f64) -> Result<i64, RollError> {
+    if v.fract() == 0.0 && v >= i64::MIN as f64 && v <= i64::MAX as f64 {
+        Ok(v as i64)
+    } else {
+        Err(RollError::NotAnInteger(v.to_string()))
+    }
+}
+
+/// The chance of rolling each face of a single die.
+fn faces(sides: &Sides<i64>) -> Result<Distribution, RollError> {
+    let count = match sides {
+        Sides::Number(n) => *n as i128,
+        Sides::Range(lo, hi) => *hi as i128 - *lo as i128 + 1,
+        Sides::Fudge | Sides::Fudge1 => 6,
+        Sides::Custom(faces) => faces.len() as i128,
+        Sides::Symbols(faces) => faces.len() as i128,
+    };
+    check_work(count as u128)?;
+
+    let evenly = |faces: Vec<i64>| {
+        let chance = 1.0 / faces.len() as f64;
+        Distribution::from_outcomes(faces.into_iter().map(|v| (v as f64, chance)))
+    };
+
+    Ok(match sides {
+        Sides::Number(n) => evenly((1..=*n).collect()),
+        Sides::Fudge => evenly(vec![-1, 0, 1]),
+        Sides::Fudge1 => evenly(vec![-1, 0, 0, 0, 0, 1]),
+        Sides::Custom(faces) => evenly(faces.clone()),
+        Sides::Range(lo, hi) => evenly((*lo..=*hi).collect()),
+        Sides::Symbols(faces) => evenly((1..=faces.len() as i64).collect()),
+    })
+}
+
+/// The value a die ends up with after `r` or `ro`. `ra` keeps both rolls, so that's
+/// handled separately.
+fn reroll(faces: &Distribution, rm: RerollModifier<i64>, limits: &Limits) -> Distribution {
+    let (target, once) = match rm {
+        RerollModifier::Reroll(t) => (t, false),
+        RerollModifier::RerollOnce(t) => (t, true),
+        RerollModifier::RerollAdd(_) | RerollModifier::None => return faces.clone(),
+    };
+
+    let rerolled = faces.split(target).0.total_probability();
+
+    // The chance of ending up with a face that does match the target is the chance of rolling
+    // it last, after every earlier roll matched. The other faces can come from any roll.
+    let (hit, miss) = if once {
+        (rerolled, 1.0 + rerolled)
+    } else {
+        (
+            rerolled.powi(limits.rerolls as i32),
+            (0..=limits.rerolls).map(|k| rerolled.powi(k as i32)).sum(),
+        )
+    };
+
+    faces.scale_by(|v| if target.matches(v as i64) { hit } else { miss })
+}
+
+/// The total of the extra dice rolled after a die explodes. Every roll is counted using
+/// `score`, and the rolls matching the target keep exploding.
+fn explosions(
+    faces: &Distribution,
+    target: Target<i64>,
+    score: impl Fn(f64) -> f64,
+    limits: &Limits,
+) -> Result<Distribution, RollError> {
+    let (hit, miss) = faces.split(target);
+    let chance = hit.total_probability();
+    let (hit, miss) = (hit.map(&score), miss.map(&score));
+
+    // Built up from the last explosion that's allowed: after that no more dice are rolled
+    let mut res = Distribution::constant(0.0);
+    let mut reached = 1.0;
+    for _ in 0..limits.explosions {
+        res = miss.clone().mix(hit.add(&res)?);
+
+        reached *= chance;
+        if reached < NEGLIGIBLE {
+            break;
+        }
+    }
+
+    Ok(res)
+}
+
+/// The chance of exactly `j` out of `n` dice matching, for every `j` from 0 up to `n`.
+fn binomial(n: u64, p: f64) -> Vec<f64> {
+    if p <= 0.0 || p >= 1.0 {
+        let mut res = vec![0.0; n as usize + 1];
+        res[if p <= 0.0 { 0 } else { n as usize }] = 1.0;
+        return res;
+    }
+
+    // In logarithms, since the separate parts get too big or small for large `n`
+    let mut ln_choose = 0.0;
+    (0..=n)
+        .map(|j| {
+            if j > 0 {
+                ln_choose += ((n - j + 1) as f64).ln() - (j as f64).ln();
+            }
+            (ln_choose + j as f64 * p.ln() + (n - j) as f64 * (1.0 - p).ln()).exp()
+        })
+        .collect()
+}
+
+/// The total of the `keep` highest or lowest out of `n` dice.
+///
+/// Goes over the faces from the best to the worst, and for every face tries every number
+/// of the remaining dice that could have rolled it. The first `keep` dice are counted.
+fn keep(
+    faces: &Distribution,
+    n: u64,
+    keep: u64,
+    highest: bool,
+    score: impl Fn(f64) -> f64,
+) -> Result<Distribution, RollError> {
+    let steps = faces.outcomes.len() as u128 * (keep.min(n) as u128 + 1);
+    check_work((n as u128 + 1).saturating_pow(2).saturating_mul(steps))?;
+
+    let mut order = faces.outcomes.clone();
+    if highest {
+        order.reverse();
+    }
+
+    // The chance of a die rolling any of the faces that haven't been handled yet
+    let mut remaining = 1.0;
+
+    // The distribution of the total so far, by how many dice have a face already
+    let mut totals = vec![Distribution::empty(); n as usize + 1];
+    totals[0] = Distribution::constant(0.0);
+
+    for (index, (v, p)) in order.iter().enumerate() {
+        let last = index == order.len() - 1;
+        let chance = if last { 1.0 } else { p / remaining };
+        remaining -= p;
+
+        let mut next = vec![Vec::new(); n as usize + 1];
+        for (done, total) in totals.iter().enumerate() {
+            if total.outcomes.is_empty() {
+                continue;
+            }
+
+            let left = n - done as u64;
+            for (j, q) in binomial(left, chance).into_iter().enumerate() {
+                if q == 0.0 {
+                    continue;
+                }
+                let kept = (j as u64).min(keep.saturating_sub(done as u64));
+                let add = kept as f64 * score(*v);
+                next[done + j].extend(total.outcomes.iter().map(|(t, r)| (t + add, r * q)));
+            }
+        }
+
+        totals = next.into_iter().map(Distribution::from_outcomes).collect();
+    }
+
+    Ok(totals.pop().unwrap())
+}
+
+/// The distribution of a single roll, with all its parameters known.
+fn dice(
+    times: i64,
+    sides: Sides<i64>,
+    fm: FilterModifier<u64>,
+    em: ExplodeModifier<i64>,
+    rm: RerollModifier<i64>,
+    sm: SuccessModifier<i64>,
+    limits: &Limits,
+) -> Result<Distribution, RollError> {
+    if times < 0 {
+        return Err(RollError::InvalidDiceCount(times));
+    }
+    let times = times as u64;
+
+    sides.check()?;
+    limits.check_roll(times, &sides)?;
+    // Keeping or dropping dice takes at least this much work, which is checked before
+    // working out the faces, since that already takes a while for dice like `d1000000`
+    if fm != FilterModifier::None {
+        check_work((times as u128 + 1).saturating_pow(2))?;
+    }
+
+    let score = |v: f64| match sm {
+        SuccessModifier::Count(success, failure) => {
+            if success.matches(v as i64) {
+                1.0
+            } else if failure.is_some_and(|f| f.matches(v as i64)) {
+                -1.0
+            } else {
+                0.0
+            }
+        }
+        SuccessModifier::None => sides.value(v as i64) as f64,
+    };
+
+    let faces = faces(&sides)?;
+    let first = reroll(&faces, rm, limits);
+
+    let (explode_target, compound) = match em {
+        ExplodeModifier::Explode(t) | ExplodeModifier::Penetrate(t) => (t, false),
+        ExplodeModifier::Compound(t) => (t, true),
+        ExplodeModifier::None => (None, false),
+    };
+    let explode_target = explode_target.unwrap_or(Target {
+        cmp: Comparison::Equal,
+        value: sides.max(),
+    });
+    let exploding = em != ExplodeModifier::None;
+
+    // Dice that explode into separate dice, or keep both rolls with `ra`, end up as
+    // more than one value
+    let extra_dice = match em {
+        ExplodeModifier::Explode(_) => Some(explosions(&faces, explode_target, score, limits)?),
+        ExplodeModifier::Penetrate(_) => Some(explosions(
+            &faces,
+            explode_target,
+            |v| score(v - 1.0),
+            limits,
+        )?),
+        _ => None,
+    };
+    let extra_reroll = match rm {
+        RerollModifier::RerollAdd(t) => Some((t, faces.map(score))),
+        _ => None,
+    };
+
+    let (hit, miss) = if exploding {
+        first.split(explode_target)
+    } else {
+        (Distribution::empty(), first)
+    };
+    let hit = if compound {
+        hit.add(&explosions(&faces, explode_target, |v| v, limits)?)?
+    } else {
+        hit
+    };
+
+    if extra_dice.is_none() && extra_reroll.is_none() {
+        let die = hit.mix(miss);
+        let n = times;
+
+        return match fm {
+            FilterModifier::None => die.map(score).repeat(times),
+            FilterModifier::KeepHighest(k) => keep(&die, n, k, true, score),
+            FilterModifier::KeepLowest(k) => keep(&die, n, k, false, score),
+            FilterModifier::DropLowest(i) => keep(&die, n, n - i.min(n), true, score),
+            FilterModifier::DropHighest(i) => keep(&die, n, n - i.min(n), false, score),
+        };
+    }
+
+    if fm != FilterModifier::None {
+        return Err(RollError::NoExactDistribution(
+            "filtered dice that explode or use ra".to_string(),
+        ));
+    }
+
+    let mut die = Distribution::empty();
+    for (part, exploded) in [(hit, true), (miss, false)] {
+        let parts = match &extra_reroll {
+            Some((target, _)) => {
+                let (rerolled, kept) = part.split(*target);
+                vec![(rerolled, true), (kept, false)]
+            }
+            None => vec![(part, false)],
+        };
+
+        for (part, rerolled) in parts {
+            let mut total = part.map(score);
+            if let Some((_, extra)) = extra_reroll.as_ref().filter(|_| rerolled) {
+                total = total.add(extra)?;
+            }
+            if let Some(extra) = extra_dice.as_ref().filter(|_| exploded) {
+                total = total.add(extra)?;
+            }
+            die = die.mix(total);
+        }
+    }
+
+    die.repeat(times)
+}
+
+/// True counts as 1 and false as 0, like it does for a rolled `Value`.
+fn truth(b: bool) -> f64 {
+    f64::from(u8::from(b))
+}
+
+impl Ast {
+    /// The distribution of whether `test` holds for two independent values.
+    fn relation(
+        &self,
+        other: &Ast,
+        test: fn(f64, f64) -> bool,
+        limits: &Limits,
+    ) -> Result<Distribution, RollError> {
+        self.distribution(limits)?
+            .combine(&other.distribution(limits)?, |x, y| truth(test(x, y)))
+    }
+
+    /// Calculates the exact distribution of the value of this expression.
+    ///
+    /// Every roll in an expression is independent of the others, so for example
+    /// `d6 - d6` is centered around 0.
+    /// `limits` cut off explosions and rerolls the same way rolling does.
+    pub fn distribution(&self, limits: &Limits) -> Result<Distribution, RollError> {
+        Ok(match self {
+            Ast::Add(l, r, _) => l.distribution(limits)?.add(&r.distribution(limits)?)?,
+            Ast::Sub(l, r, _) => l
+                .distribution(limits)?
+                .combine(&r.distribution(limits)?, |x, y| x - y)?,
+            Ast::Mul(l, r, _) => l
+                .distribution(limits)?
+                .combine(&r.distribution(limits)?, |x, y| x * y)?,
+            Ast::Div(l, r, _) => l
+                .distribution(limits)?
+                .combine(&r.distribution(limits)?, |x, y| x / y)?,
+            Ast::Mod(l, r, _) => l
+                .distribution(limits)?
+                .combine(&r.distribution(limits)?, |x, y| x % y)?,
+            Ast::IDiv(l, r, _) => l
+                .distribution(limits)?
+                .combine(&r.distribution(limits)?, |x, y| (x / y).floor())?,
+            Ast::Power(l, r, _) => l
+                .distribution(limits)?
+                .combine(&r.distribution(limits)?, f64::powf)?,
+            Ast::Minus(l, _) => l.distribution(limits)?.map(|x| -x),
+            Ast::Less(l, r, _) => l.relation(r, |x, y| x < y, limits)?,
+            Ast::LessEqual(l, r, _) => l.relation(r, |x, y| x <= y, limits)?,
+            Ast::Equal(l, r, _) => l.relation(r, |x, y| x == y, limits)?,
+            Ast::NotEqual(l, r, _) => l.relation(r, |x, y| x != y, limits)?,
+            Ast::GreaterEqual(l, r, _) => l.relation(r, |x, y| x >= y, limits)?,
+            Ast::Greater(l, r, _) => l.relation(r, |x, y| x > y, limits)?,
+            Ast::And(l, r, _) => l.relation(r, |x, y| x != 0.0 && y != 0.0, limits)?,
+            Ast::Or(l, r, _) => l.relation(r, |x, y| x != 0.0 || y != 0.0, limits)?,
+            Ast::Not(l, _) => l.distribution(limits)?.map(|x| truth(x == 0.0)),
+            Ast::If(c, then, otherwise, _) => {
+                // Only the branch that's picked is rolled, so a branch that's never picked
+                // doesn't need to have a distribution
+                let chance = c.distribution(limits)?.probability(1.0);
+                let mut res = Distribution::empty();
+                for (branch, p) in [(then, chance), (otherwise, 1.0 - chance)] {
+                    if p > 0.0 {
+                        res = res.mix(Distribution {
+                            outcomes: branch.distribution(limits)?.scale(p).collect(),
+                        });
+                    }
+                }
+                res
+            }
+            Ast::Const(v, _) => Distribution::constant(v.clone().into()),
+            Ast::Call(function, args, _) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.distribution(limits))
+                    .collect::<Result<Vec<_>, _>>()?;
+                // The parser checked the number of arguments
+                let (first, rest) = args.split_first().unwrap();
+
+                match function {
+                    Function::Floor => first.map(f64::floor),
+                    Function::Ceil => first.map(f64::ceil),
+                    Function::Round => first.map(f64::round),
+                    Function::Abs => first.map(f64::abs),
+                    Function::Min => rest
+                        .iter()
+                        .try_fold(first.clone(), |res, arg| res.combine(arg, f64::min))?,
+                    Function::Max => rest
+                        .iter()
+                        .try_fold(first.clone(), |res, arg| res.combine(arg, f64::max))?,
+                    Function::Clamp => {
+                        rest[0].combine(&first.combine(&rest[1], f64::min)?, f64::max)?
+                    }
+                }
+            }
+
+            Ast::Dice(times, sides, fm, em, rm, sm, span) => {
+                // The parameters of a roll can be rolls themselves, so every combination of
+                // their values is tried. Every parameter is replaced by its index in `params`.
+                let mut params = Vec::new();
+                let mut param = |ast: Box<Ast>| -> Result<usize, RollError> {
+                    params.push(ast.distribution(limits)?);
+                    Ok(params.len() - 1)
+                };
+
+                let times = param(
+                    times
+                        .clone()
+                        .unwrap_or_else(|| Box::new(Ast::Const(Value::Int(1), *span))),
+                )?;
+                let sides = sides
+                    .clone()
+                    .unwrap_or_else(|| {
+                        Sides::Number(Box::new(Ast::Const(Value::Int(DEFAULT_SIDES), *span)))
+                    })
+                    .map(&mut param)
+                    .swap()?;
+                let fm = fm.clone().map(&mut param).swap()?;
+                let em = em.clone().map(&mut param).swap()?;
+                let rm = rm.clone().map(&mut param).swap()?;
+                let sm = sm.clone().map(&mut param).swap()?;
+
+                let mut combinations = vec![(Vec::new(), 1.0)];
+                for dist in &params {
+                    check_work(combinations.len() as u128 * dist.outcomes.len() as u128)?;
+                    combinations = combinations
+                        .into_iter()
+                        .flat_map(|(values, p): (Vec<f64>, f64)| {
+                            dist.outcomes.iter().map(move |(v, q)| {
+                                let mut values = values.clone();
+                                values.push(*v);
+                                (values, p * q)
+                            })
+                        })
+                        .collect();
+                }
+
+                let mut res = Distribution::empty();
+                for (values, p) in combinations {
+                    let value = |i: usize| to_int(values[i]);
+                    let roll = dice(
+                        value(times)?,
+                        sides.clone().map(value).swap()?,
+                        fm.map(value).swap()?.check()?,
+                        em.map(value).swap()?,
+                        rm.map(value).swap()?,
+                        sm.map(value).swap()?,
+                        limits,
+                    )?;
+                    res = res.mix(Distribution {
+                        outcomes: roll.scale(p).collect(),
+                    });
+                }
+                res
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Distribution;
+    use crate::interpreter::Ast;
+    use crate::limits::Limits;
+    use crate::parser::Parser;
+    use crate::roll::tests::SeededRng;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    fn parse(s: &str) -> Ast {
+        let mut p = Parser::new(s);
+        p.advanced = true;
+        p.parse().unwrap()
+    }
+
+    fn dist(s: &str) -> Distribution {
+        parse(s).distribution(&Limits::DEFAULT).unwrap()
+    }
+
+    /// Checks two distributions have the same outcomes, calculated in different ways.
+    fn assert_same(a: &Distribution, b: &Distribution) {
+        assert_eq!(a.outcomes.len(), b.outcomes.len());
+        for ((v, p), (w, q)) in a.outcomes.iter().zip(&b.outcomes) {
+            assert_eq!(v, w);
+            assert!(close(*p, *q), "{}: {} != {}", v, p, q);
+        }
+    }
+
+    #[test]
+    fn test_single_die() {
+        let d = dist("d6");
+        assert_eq!(d.min(), 1.0);
+        assert_eq!(d.max(), 6.0);
+        assert!(close(d.mean(), 3.5));
+        assert!(close(d.variance(), 35.0 / 12.0));
+        assert!(close(d.probability(4.0), 1.0 / 6.0));
+    }
+
+    #[test]
+    fn test_sum() {
+        let d = dist("2d6 + 3");
+        assert_eq!(d.min(), 5.0);
+        assert_eq!(d.max(), 15.0);
+        assert!(close(d.probability(10.0), 6.0 / 36.0));
+        assert!(close(d.at_least(14.0), 3.0 / 36.0));
+        assert!(close(d.at_most(5.0), 1.0 / 36.0));
+
+        let d = dist("d6 - d6");
+        assert!(close(d.mean(), 0.0));
+        assert!(close(d.variance(), 2.0 * 35.0 / 12.0));
+    }
+
+    #[test]
+    fn test_keep() {
+        // Advantage: at least one of the two dice has to roll 10 or more
+        let d = dist("2d20kh1 + 5");
+        assert!(close(d.at_least(15.0), 1.0 - 0.45 * 0.45));
+
+        let d = dist("2d20kl1");
+        assert!(close(d.at_least(10.0), 0.55 * 0.55));
+
+        assert!(close(dist("4d6kh3").mean(), 15869.0 / 1296.0));
+        assert_same(&dist("4d6dl1"), &dist("4d6kh3"));
+        assert_same(&dist("3d6dh1"), &dist("3d6kl2"));
+        assert_same(&dist("3d6kh5"), &dist("3d6"));
+    }
+
+    #[test]
+    fn test_modifiers() {
+        assert!(close(dist("d6r1").mean(), 4.0));
+        assert!(close(dist("d6ro1").mean(), 3.5 + 2.5 / 6.0));
+        assert!(close(dist("d6ra1").mean(), 3.5 + 3.5 / 6.0));
+
+        // Every die explodes on a 6 with a chance of 1/6
+        assert!(close(dist("d6!").mean(), 4.2));
+        assert!(close(dist("d6!!").mean(), 4.2));
+        assert!(close(dist("d6!p").mean(), 4.2 - 0.2));
+        assert_eq!(dist("d6!!").min(), 1.0);
+        assert!(close(dist("d6!!").probability(6.0), 0.0));
+
+        assert!(close(dist("dF").mean(), 0.0));
+        assert!(close(dist("dF").variance(), 2.0 / 3.0));
+        assert!(close(dist("d[0..9]").mean(), 4.5));
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = Limits {
+            explosions: 1,
+            rerolls: 0,
+            dice_per_roll: 10,
+            ..Limits::DEFAULT
+        };
+        let dist = |s: &str| parse(s).distribution(&limits);
+
+        // A die explodes once at most, like when it's rolled with these limits
+        let d = dist("d6!").unwrap();
+        assert_eq!(d.max(), 12.0);
+        assert!(close(d.mean(), 3.5 + 3.5 / 6.0));
+        assert_eq!(dist("d6!!").unwrap().max(), 12.0);
+        assert_same(&dist("d6r1").unwrap(), &dist("d6").unwrap());
+        assert!(dist("10d6").is_ok());
+        assert!(dist("11d6").is_err());
+    }
+
+    #[test]
+    fn test_pool() {
+        let d = dist("3d10>8f1");
+        assert_eq!(d.min(), -3.0);
+        assert_eq!(d.max(), 3.0);
+        assert!(close(d.mean(), 3.0 * (0.2 - 0.1)));
+        assert!(close(d.probability(3.0), 0.2f64.powi(3)));
+    }
+
+    #[test]
+    fn test_random_parameters() {
+        // Either one or two d6
+        let d = dist("(d2)d6");
+        assert!(close(d.mean(), 0.5 * 3.5 + 0.5 * 7.0));
+        assert!(close(d.probability(12.0), 0.5 / 36.0));
+    }
+
+    #[test]
+    fn test_operators() {
+        assert!(close(dist("d4 * 2").mean(), 5.0));
+        assert!(close(dist("d4 / 2").mean(), 1.25));
+        assert!(close(dist("d4 // 2").mean(), 1.0));
+        assert!(close(dist("d4 mod 2").mean(), 0.5));
+        assert!(close(dist("2 ** d2").mean(), 3.0));
+        assert!(close(dist("-d4").mean(), -2.5));
+    }
+
+    #[test]
+    fn test_functions() {
+        assert!(close(dist("floor(d6 / 2)").mean(), 1.5));
+        assert!(close(dist("ceil(d6 / 2)").mean(), 2.0));
+        assert!(close(dist("abs(d4 - 3)").mean(), 1.0));
+
+        let d = dist("max(1, d4 - 2)");
+        assert!(close(d.probability(1.0), 0.75));
+        assert!(close(d.probability(2.0), 0.25));
+        assert!(close(dist("min(d6, d6)").mean(), 91.0 / 36.0));
+        assert_same(&dist("max(d20, d20)"), &dist("2d20kh1"));
+        assert_same(&dist("clamp(d6, 2, 5)"), &dist("max(2, min(d6, 5))"));
+    }
+
+    #[test]
+    fn test_conditions() {
+        // True counts as 1, so the mean is the chance of the condition
+        assert!(close(dist("d20 >= 15").mean(), 0.3));
+        assert!(close(dist("d6 != 6").mean(), 5.0 / 6.0));
+        assert!(close(dist("d6 > 3 and d6 > 3").mean(), 0.25));
+        assert!(close(dist("d6 > 3 or d6 > 3").mean(), 0.75));
+        assert!(close(dist("not d4 == 1").mean(), 0.75));
+
+        let d = dist("d20 + 5 >= 15 ? 2d6 + 3 : 0");
+        assert!(close(d.probability(0.0), 0.45));
+        assert!(close(d.mean(), 0.55 * 10.0));
+        // The branch that's never picked isn't calculated
+        assert_same(&dist("1 < 2 ? d6 : 1000000d1000"), &dist("d6"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |s: &str| parse(s).distribution(&Limits::DEFAULT).is_err();
+        assert!(err("d6!kh1"));
+        assert!(err("1000000d1000"));
+        assert!(err("2d0"));
+    }
+
+    #[test]
+    fn test_matches_rolls() {
+        const ROLLS: u64 = 20_000;
+        let mut rng = SeededRng { state: 99 };
+
+        for s in ["4d6!>5", "5d8r<3kl3", "2d10!!>8>9f1", "3d6ra6", "(d4)d6dh1"] {
+            let ast = parse(s);
+            let d = ast.distribution(&Limits::DEFAULT).unwrap();
+            let mut total = 0.0;
+            for _ in 0..ROLLS {
+                let value = ast.clone().interp_with_rng(&mut Vec::new(), &mut rng);
+                total += f64::from(value.unwrap());
+            }
+
+            // Comfortably more than four standard errors
+            let error = 5.0 * d.std_dev() / (ROLLS as f64).sqrt();
+            let mean = total / ROLLS as f64;
+            assert!(
+                (mean - d.mean()).abs() < error,
+                "{}: rolled an average of {}, expected {}",
+                s,
+                mean,
+                d.mean()
+            );
+        }
+    }
+}
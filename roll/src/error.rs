@@ -0,0 +1,131 @@
+use core::fmt;
+
+/// Everything that can go wrong parsing, rolling or analyzing a dice expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollError {
+    /// The expression isn't valid dice notation.
+    Parse {
+        /// The expression that was parsed.
+        source: String,
+        /// The position of the character that couldn't be parsed.
+        pos: u64,
+        /// The characters or kinds of token that could have come at that position, sorted.
+        expected: Vec<String>,
+        /// What the parser was trying to do, can be empty.
+        message: String,
+    },
+    /// A negative number of dice.
+    InvalidDiceCount(i64),
+    /// A die with zero (or fewer) sides, like `d0`.
+    ZeroSides(i64),
+    /// A negative number of dice to keep or drop, like `4d6kh(-1)`.
+    InvalidFilterCount(i64),
+    /// A range die whose lower bound is above its upper bound, like `d[6..1]`.
+    EmptyRange(i64, i64),
+    /// A number of dice, sides or a modifier argument that isn't a whole number.
+    NotAnInteger(String),
+    /// True or false where a number is needed, like in `(d6 > 3) + 1`.
+    NotANumber(String),
+    /// A number where true or false is needed, like in `not 3`.
+    NotABoolean(String),
+    /// A value that got too large to represent.
+    Overflow,
+    /// Calculating the result would take too much work or memory.
+    LimitExceeded(String),
+    /// The exact distribution of the expression can't be calculated, but it can be simulated.
+    NoExactDistribution(String),
+    /// A simulation was asked to roll 0 times.
+    NoSamples,
+}
+
+impl fmt::Display for RollError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse {
+                source,
+                pos,
+                expected,
+                message,
+            } => {
+                writeln!(f, "{}", source)?;
+                writeln!(f, "{}^", " ".repeat(*pos as usize))?;
+
+                if !expected.is_empty() {
+                    writeln!(f, "An error occurred: unexpected character.")?;
+                    writeln!(f, "Expected any of: [{}]", expected.join(", "))?;
+                    writeln!(f)?;
+                }
+
+                if !message.is_empty() {
+                    writeln!(f, "{}", message)?;
+                }
+                Ok(())
+            }
+            Self::InvalidDiceCount(n) => write!(f, "Can't roll {} dice", n),
+            Self::ZeroSides(n) => write!(f, "Can't roll {} sided die", n),
+            Self::InvalidFilterCount(n) => write!(f, "Can't keep or drop {} dice", n),
+            Self::EmptyRange(lo, hi) => write!(f, "Can't roll a die from {} up to {}", lo, hi),
+            Self::NotAnInteger(v) => write!(f, "{}: couldn't be parsed as int", v),
+            Self::NotANumber(v) => write!(f, "{}: isn't a number", v),
+            Self::NotABoolean(v) => write!(f, "{}: isn't true or false", v),
+            Self::Overflow => write!(f, "The result is too large"),
+            Self::LimitExceeded(what) => write!(f, "Too many {}", what),
+            Self::NoExactDistribution(why) => {
+                write!(f, "Can't calculate the exact distribution of {}", why)
+            }
+            Self::NoSamples => write!(f, "Can't simulate 0 rolls"),
+        }
+    }
+}
+
+impl std::error::Error for RollError {}
+
+#[cfg(test)]
+mod tests {
+    use super::RollError;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_parse_error() {
+        match Parser::new("2d6 + x").parse() {
+            Err(RollError::Parse { pos, expected, .. }) => {
+                assert_eq!(pos, 6);
+                assert!(expected.contains(&"0-9".to_string()));
+                assert!(expected.contains(&"(".to_string()));
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+
+        let e = Parser::new("1)").parse().unwrap_err();
+        assert_eq!(e.to_string(), "1)\n ^\nunexpected trailing character(s)\n");
+    }
+
+    #[test]
+    fn test_roll_errors() {
+        let interp = |s: &str| {
+            let mut p = Parser::new(s);
+            p.advanced = true;
+            p.parse().unwrap().interp(&mut Vec::new()).unwrap_err()
+        };
+
+        assert_eq!(interp("2d0"), RollError::ZeroSides(0));
+        assert_eq!(interp("d(-6)"), RollError::ZeroSides(-6));
+        assert_eq!(interp("(-1)d6"), RollError::InvalidDiceCount(-1));
+        assert_eq!(interp("4d6kh(-1)"), RollError::InvalidFilterCount(-1));
+        assert_eq!(interp("4d6kl(1-2)"), RollError::InvalidFilterCount(-1));
+        assert_eq!(interp("4d6dh(-5)"), RollError::InvalidFilterCount(-5));
+        assert_eq!(
+            interp("4d6dl(-1)").to_string(),
+            "Can't keep or drop -1 dice"
+        );
+        assert_eq!(interp("d[6..1]"), RollError::EmptyRange(6, 1));
+        assert_eq!(
+            interp("(1.5)d6"),
+            RollError::NotAnInteger("1.5".to_string())
+        );
+        assert_eq!(
+            interp("d(7/2)").to_string(),
+            "3.5: couldn't be parsed as int"
+        );
+    }
+}
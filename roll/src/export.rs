@@ -0,0 +1,122 @@
+use crate::distribution::Distribution;
+use crate::simulation::Analysis;
+use std::fmt::Write;
+
+/// JSON has no infinity or NaN, which division by zero can give.
+fn json_number(v: f64) -> String {
+    if v.is_finite() {
+        v.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+impl Distribution {
+    /// Every outcome on its own line as `outcome,probability,cumulative`, where the
+    /// cumulative probability is the chance of that outcome or less.
+    pub fn to_csv(&self) -> String {
+        let mut res = "outcome,probability,cumulative\n".to_string();
+        let mut cumulative = 0.0;
+        for (v, p) in self.outcomes() {
+            cumulative += p;
+            writeln!(res, "{},{},{}", v, p, cumulative).unwrap();
+        }
+        res
+    }
+
+    /// The mean, standard deviation, range and outcomes as a JSON object.
+    pub fn to_json(&self) -> String {
+        format!("{{{}}}", self.json_fields())
+    }
+
+    fn json_fields(&self) -> String {
+        let mut outcomes = Vec::new();
+        let mut cumulative = 0.0;
+        for (v, p) in self.outcomes() {
+            cumulative += p;
+            outcomes.push(format!(
+                r#"{{"outcome":{},"probability":{},"cumulative":{}}}"#,
+                json_number(*v),
+                p,
+                cumulative
+            ));
+        }
+
+        format!(
+            r#""mean":{},"std_dev":{},"min":{},"max":{},"outcomes":[{}]"#,
+            json_number(self.mean()),
+            json_number(self.std_dev()),
+            json_number(self.min()),
+            json_number(self.max()),
+            outcomes.join(",")
+        )
+    }
+}
+
+impl Analysis {
+    pub fn to_csv(&self) -> String {
+        self.distribution().to_csv()
+    }
+
+    /// Like `Distribution::to_json`, but also says whether the distribution is exact,
+    /// or else how many rolls were simulated.
+    pub fn to_json(&self) -> String {
+        match self {
+            Self::Exact(d) => format!(r#"{{"exact":true,{}}}"#, d.json_fields()),
+            Self::Simulated(s) => format!(
+                r#"{{"exact":false,"samples":{},{}}}"#,
+                s.samples(),
+                s.distribution().json_fields()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::Parser;
+    use crate::roll::tests::SeededRng;
+    use crate::simulation::Method;
+
+    fn analyze(s: &str, method: Method) -> crate::simulation::Analysis {
+        let ast = Parser::new(s).parse().unwrap();
+        ast.analyze(
+            method,
+            &mut SeededRng { state: 0 },
+            &crate::limits::Limits::DEFAULT,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_csv() {
+        assert_eq!(
+            analyze("d4", Method::Exact).to_csv(),
+            "outcome,probability,cumulative\n1,0.25,0.25\n2,0.25,0.5\n3,0.25,0.75\n4,0.25,1\n"
+        );
+        assert_eq!(
+            analyze("d4 / 2", Method::Exact).to_csv().lines().nth(1),
+            Some("0.5,0.25,0.25")
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            analyze("d2 * 2", Method::Exact).to_json(),
+            concat!(
+                r#"{"exact":true,"mean":3,"std_dev":1,"min":2,"max":4,"outcomes":["#,
+                r#"{"outcome":2,"probability":0.5,"cumulative":0.5},"#,
+                r#"{"outcome":4,"probability":0.5,"cumulative":1}]}"#
+            )
+        );
+
+        let sim = analyze("d6", Method::Simulate(100)).to_json();
+        assert!(sim.starts_with(r#"{"exact":false,"samples":100,"mean":"#));
+
+        // Division by zero doesn't give a number
+        assert!(analyze("1 / (d2 - 1)", Method::Exact)
+            .to_json()
+            .contains(r#""outcome":null"#));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::error::RollError;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 
@@ -62,3 +63,266 @@ impl<T, E> FilterModifier<Result<T, E>> {
         })
     }
 }
+
+impl FilterModifier<i64> {
+    /// Checks the number of dice to keep or drop isn't negative. Keeping 0 dice gives a
+    /// total of 0 and dropping 0 dice keeps all of them, keeping or dropping more dice than
+    /// were rolled keeps or drops all of them.
+    pub fn check(self) -> Result<FilterModifier<u64>, RollError> {
+        self.map(|n| u64::try_from(n).map_err(|_| RollError::InvalidFilterCount(n)))
+            .swap()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Comparison {
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+}
+
+impl Comparison {
+    pub fn matches<T: PartialOrd>(self, lhs: T, rhs: T) -> bool {
+        match self {
+            Self::Equal => lhs == rhs,
+            Self::Greater => lhs > rhs,
+            Self::GreaterEqual => lhs >= rhs,
+            Self::Less => lhs < rhs,
+            Self::LessEqual => lhs <= rhs,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "=",
+            Self::Greater => ">",
+            Self::GreaterEqual => ">=",
+            Self::Less => "<",
+            Self::LessEqual => "<=",
+        })
+    }
+}
+
+/// A condition a single die is checked against, like the `>8` in `d10!>8`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Target<T> {
+    pub cmp: Comparison,
+    pub value: T,
+}
+
+impl<T: PartialOrd + Copy> Target<T> {
+    pub fn matches(&self, v: T) -> bool {
+        self.cmp.matches(v, self.value)
+    }
+}
+
+impl<T: Display> Display for Target<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // A bare number already means "equal to", so don't print the `=`.
+        if self.cmp != Comparison::Equal {
+            self.cmp.fmt(f)?;
+        }
+        self.value.fmt(f)
+    }
+}
+
+impl<T> Target<T> {
+    pub(crate) fn map<F, U>(self, f: F) -> Target<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Target {
+            cmp: self.cmp,
+            value: f(self.value),
+        }
+    }
+}
+
+impl<T, E> Target<Result<T, E>> {
+    pub(crate) fn swap(self) -> Result<Target<T>, E> {
+        Ok(Target {
+            cmp: self.cmp,
+            value: self.value?,
+        })
+    }
+}
+
+/// Rolls extra dice for every die matching the target. Without a target
+/// a die explodes when it rolls its maximum.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExplodeModifier<T> {
+    /// Every extra roll is added as a separate die.
+    Explode(Option<Target<T>>),
+    /// Every extra roll is added to the value of the die that exploded.
+    Compound(Option<Target<T>>),
+    /// Like `Explode`, but every extra roll is one lower.
+    Penetrate(Option<Target<T>>),
+    None,
+}
+
+impl<T: Display> Display for ExplodeModifier<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Explode(t) | Self::Compound(t) | Self::Penetrate(t) => {
+                match self {
+                    Self::Compound(_) => write!(f, "!!")?,
+                    Self::Penetrate(_) => write!(f, "!p")?,
+                    _ => write!(f, "!")?,
+                }
+                if let Some(t) = t {
+                    t.fmt(f)?;
+                }
+            }
+            Self::None => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> ExplodeModifier<T> {
+    pub(crate) fn map<F, U>(self, f: F) -> ExplodeModifier<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        match self {
+            Self::Explode(t) => ExplodeModifier::Explode(t.map(|t| t.map(f))),
+            Self::Compound(t) => ExplodeModifier::Compound(t.map(|t| t.map(f))),
+            Self::Penetrate(t) => ExplodeModifier::Penetrate(t.map(|t| t.map(f))),
+            Self::None => ExplodeModifier::None,
+        }
+    }
+}
+
+impl<T, E> ExplodeModifier<Result<T, E>> {
+    pub(crate) fn swap(self) -> Result<ExplodeModifier<T>, E> {
+        Ok(match self {
+            ExplodeModifier::Explode(t) => {
+                ExplodeModifier::Explode(t.map(Target::swap).transpose()?)
+            }
+            ExplodeModifier::Compound(t) => {
+                ExplodeModifier::Compound(t.map(Target::swap).transpose()?)
+            }
+            ExplodeModifier::Penetrate(t) => {
+                ExplodeModifier::Penetrate(t.map(Target::swap).transpose()?)
+            }
+            ExplodeModifier::None => ExplodeModifier::None,
+        })
+    }
+}
+
+/// Rolls a die again when it matches the target.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RerollModifier<T> {
+    /// Keeps rerolling until the die no longer matches.
+    Reroll(Target<T>),
+    /// Rerolls at most once, keeping the second roll.
+    RerollOnce(Target<T>),
+    /// Rerolls at most once, keeping both rolls.
+    RerollAdd(Target<T>),
+    None,
+}
+
+impl<T: Display> Display for RerollModifier<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Reroll(t) => {
+                write!(f, "r")?;
+                t.fmt(f)?;
+            }
+            Self::RerollOnce(t) => {
+                write!(f, "ro")?;
+                t.fmt(f)?;
+            }
+            Self::RerollAdd(t) => {
+                write!(f, "ra")?;
+                t.fmt(f)?;
+            }
+            Self::None => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> RerollModifier<T> {
+    pub(crate) fn map<F, U>(self, f: F) -> RerollModifier<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        match self {
+            Self::Reroll(t) => RerollModifier::Reroll(t.map(f)),
+            Self::RerollOnce(t) => RerollModifier::RerollOnce(t.map(f)),
+            Self::RerollAdd(t) => RerollModifier::RerollAdd(t.map(f)),
+            Self::None => RerollModifier::None,
+        }
+    }
+}
+
+impl<T, E> RerollModifier<Result<T, E>> {
+    pub(crate) fn swap(self) -> Result<RerollModifier<T>, E> {
+        Ok(match self {
+            RerollModifier::Reroll(t) => RerollModifier::Reroll(t.swap()?),
+            RerollModifier::RerollOnce(t) => RerollModifier::RerollOnce(t.swap()?),
+            RerollModifier::RerollAdd(t) => RerollModifier::RerollAdd(t.swap()?),
+            RerollModifier::None => RerollModifier::None,
+        })
+    }
+}
+
+/// Turns a roll into a dice pool: the total becomes the number of dice matching
+/// the success target, minus the number of dice matching the optional failure target.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum SuccessModifier<T> {
+    Count(Target<T>, Option<Target<T>>),
+    None,
+}
+
+impl<T: Display> Display for SuccessModifier<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count(success, failure) => {
+                // The success target always needs its comparison, otherwise it would
+                // be read as part of the modifier before it.
+                success.cmp.fmt(f)?;
+                success.value.fmt(f)?;
+                if let Some(failure) = failure {
+                    write!(f, "f")?;
+                    failure.fmt(f)?;
+                }
+            }
+            Self::None => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl<T> SuccessModifier<T> {
+    pub(crate) fn map<F, U>(self, mut f: F) -> SuccessModifier<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        match self {
+            Self::Count(success, failure) => {
+                SuccessModifier::Count(success.map(&mut f), failure.map(|t| t.map(f)))
+            }
+            Self::None => SuccessModifier::None,
+        }
+    }
+}
+
+impl<T, E> SuccessModifier<Result<T, E>> {
+    pub(crate) fn swap(self) -> Result<SuccessModifier<T>, E> {
+        Ok(match self {
+            SuccessModifier::Count(success, failure) => {
+                SuccessModifier::Count(success.swap()?, failure.map(Target::swap).transpose()?)
+            }
+            SuccessModifier::None => SuccessModifier::None,
+        })
+    }
+}
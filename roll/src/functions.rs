@@ -0,0 +1,184 @@
+use crate::error::RollError;
+use crate::interpreter::Value;
+use core::cmp::Ordering;
+use core::fmt;
+
+/// A built-in function that can be called in an expression, like `floor(d20 / 2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Floor,
+    Ceil,
+    Round,
+    Abs,
+    Min,
+    Max,
+    Clamp,
+}
+
+/// Every function with its name and the fewest and most arguments it takes.
+const FUNCTIONS: &[(&str, Function, usize, usize)] = &[
+    ("floor", Function::Floor, 1, 1),
+    ("ceil", Function::Ceil, 1, 1),
+    ("round", Function::Round, 1, 1),
+    ("abs", Function::Abs, 1, 1),
+    ("min", Function::Min, 1, usize::MAX),
+    ("max", Function::Max, 1, usize::MAX),
+    ("clamp", Function::Clamp, 3, 3),
+];
+
+impl Function {
+    pub fn from_name(name: &str) -> Option<Self> {
+        FUNCTIONS
+            .iter()
+            .find(|(n, ..)| *n == name)
+            .map(|(_, f, ..)| *f)
+    }
+
+    fn entry(self) -> (&'static str, usize, usize) {
+        // Every function is in the table
+        let (name, _, fewest, most) = FUNCTIONS.iter().find(|(_, f, ..)| *f == self).unwrap();
+        (name, *fewest, *most)
+    }
+
+    pub fn name(self) -> &'static str {
+        self.entry().0
+    }
+
+    /// Checks the function can be called with `count` arguments, and says how many it
+    /// takes if it can't.
+    pub fn check_arguments(self, count: usize) -> Result<(), String> {
+        let (name, fewest, most) = self.entry();
+        let plural = |n| if n == 1 { "argument" } else { "arguments" };
+
+        if count < fewest {
+            if most == usize::MAX {
+                Err(format!(
+                    "`{}` takes at least {} {}",
+                    name,
+                    fewest,
+                    plural(fewest)
+                ))
+            } else {
+                Err(format!("`{}` takes {} {}", name, fewest, plural(fewest)))
+            }
+        } else if count > most {
+            Err(format!("`{}` takes {} {}", name, most, plural(most)))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Calls the function. Rounding gives an integer, the other functions give one of their
+    /// arguments or, for `abs`, a value of the same kind.
+    pub(crate) fn apply(self, args: Vec<Value>) -> Result<Value, RollError> {
+        let mut args = args.into_iter();
+        // The parser checked the number of arguments
+        let mut next = || args.next().unwrap();
+
+        Ok(match self {
+            Function::Floor => next().floor(),
+            Function::Ceil => next().ceil(),
+            Function::Round => next().round(),
+            Function::Abs => match next() {
+                Value::Float(f) => Value::Float(f.abs()),
+                v if v.compare(&Value::Int(0)) == Some(Ordering::Less) => (-v)?,
+                v => v,
+            },
+            Function::Min => pick(next(), args, Ordering::Less),
+            Function::Max => pick(next(), args, Ordering::Greater),
+            Function::Clamp => {
+                let (value, lo, hi) = (next(), next(), next());
+                pick(lo, [pick(value, [hi], Ordering::Less)], Ordering::Greater)
+            }
+        })
+    }
+}
+
+/// The first value that's more `ordering` than all others. Values that can't be compared,
+/// like NaN, are skipped.
+fn pick(first: Value, rest: impl IntoIterator<Item = Value>, ordering: Ordering) -> Value {
+    rest.into_iter().fold(first, |best, v| {
+        if v.compare(&best) == Some(ordering) {
+            v
+        } else {
+            best
+        }
+    })
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Function;
+    use crate::interpreter::Value;
+    use crate::parser::Parser;
+
+    fn eval(s: &str) -> Value {
+        Parser::new(s)
+            .parse()
+            .unwrap()
+            .interp(&mut Vec::new())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rounding() {
+        assert_eq!(eval("floor(7 / 2)"), Value::Int(3));
+        assert_eq!(eval("floor(-7 / 2)"), Value::Int(-4));
+        assert_eq!(eval("ceil(7 / 2)"), Value::Int(4));
+        assert_eq!(eval("round(7 / 2)"), Value::Int(4));
+        assert_eq!(eval("round(-2.5)"), Value::Int(-3));
+        assert_eq!(eval("round(2.4)"), Value::Int(2));
+        assert_eq!(eval("floor(5)"), Value::Int(5));
+        assert_eq!(eval("ceil(1 / 0)"), Value::Float(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_abs() {
+        assert_eq!(eval("abs(-3)"), Value::Int(3));
+        assert_eq!(eval("abs(3)"), Value::Int(3));
+        assert_eq!(eval("abs(-2.5)"), Value::Float(2.5));
+    }
+
+    #[test]
+    fn test_min_max() {
+        assert_eq!(eval("max(1, 4 - 6)"), Value::Int(1));
+        assert_eq!(eval("min(1, 4 - 6)"), Value::Int(-2));
+        assert_eq!(eval("max(2, 2.5, -1)"), Value::Float(2.5));
+        assert_eq!(eval("min(7)"), Value::Int(7));
+        // The first of equal values is kept
+        assert_eq!(eval("max(2, 2.0)"), Value::Int(2));
+    }
+
+    #[test]
+    fn test_clamp() {
+        assert_eq!(eval("clamp(5, 1, 3)"), Value::Int(3));
+        assert_eq!(eval("clamp(-5, 1, 3)"), Value::Int(1));
+        assert_eq!(eval("clamp(2, 1, 3)"), Value::Int(2));
+    }
+
+    #[test]
+    fn test_arguments() {
+        assert_eq!(Function::from_name("clamp"), Some(Function::Clamp));
+        assert_eq!(Function::from_name("sqrt"), None);
+
+        assert!(Function::Floor.check_arguments(1).is_ok());
+        assert_eq!(
+            Function::Floor.check_arguments(2),
+            Err("`floor` takes 1 argument".to_string())
+        );
+        assert_eq!(
+            Function::Max.check_arguments(0),
+            Err("`max` takes at least 1 argument".to_string())
+        );
+        assert_eq!(
+            Function::Clamp.check_arguments(2),
+            Err("`clamp` takes 3 arguments".to_string())
+        );
+    }
+}
@@ -1,18 +1,33 @@
-use crate::filtermodifier::FilterModifier;
+use crate::error::RollError;
+use crate::filtermodifier::{ExplodeModifier, FilterModifier, RerollModifier, SuccessModifier};
+use crate::functions::Function;
+use crate::limits::Limits;
 use crate::roll::{roll_die, Roll};
+use crate::sides::Sides;
+use crate::span::Span;
+use core::cmp::Ordering;
 use core::fmt;
 use core::option::Option::Some;
 use core::result::Result::{Err, Ok};
+#[cfg(feature = "bigint")]
+use num_bigint::BigInt;
+#[cfg(feature = "bigint")]
+use num_traits::{FromPrimitive, ToPrimitive};
+use rand_core::{OsRng, RngCore};
 use std::fmt::Display;
-use std::num::NonZeroU64;
-use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::ops::{Add, Div, Mul, Neg, Not, Rem, Sub};
 
-pub const DEFAULT_SIDES: &str = "20";
+pub const DEFAULT_SIDES: i64 = 20;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Float(f64),
     Int(i64),
+    /// The result of a comparison, or of `and`, `or` and `not`.
+    Bool(bool),
+    /// An integer that doesn't fit in an `Int`.
+    #[cfg(feature = "bigint")]
+    Big(BigInt),
 }
 
 impl From<Value> for f64 {
@@ -20,6 +35,9 @@ impl From<Value> for f64 {
         match v {
             Value::Int(i) => i as f64,
             Value::Float(f) => f,
+            Value::Bool(b) => f64::from(u8::from(b)),
+            #[cfg(feature = "bigint")]
+            Value::Big(b) => b.to_f64().unwrap_or(f64::NAN),
         }
     }
 }
@@ -29,251 +47,683 @@ impl fmt::Display for Value {
         match self {
             Self::Float(v) => f.write_str(&v.to_string()),
             Self::Int(v) => f.write_str(&v.to_string()),
+            Self::Bool(v) => f.write_str(&v.to_string()),
+            #[cfg(feature = "bigint")]
+            Self::Big(v) => f.write_str(&v.to_string()),
         }
     }
 }
 
+/// An operation on two integers that don't fit in an `Int`, or whose result doesn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Add,
+    Sub,
+    Mul,
+    /// The remainder of two `Int`s always fits, so this is only needed for `Big`s.
+    #[cfg(feature = "bigint")]
+    Rem,
+    /// Division rounded down.
+    IDiv,
+    Pow,
+}
+
+impl Value {
+    #[cfg(not(feature = "bigint"))]
+    pub(crate) fn parse_int(s: &str) -> Option<Value> {
+        s.parse().ok().map(Value::Int)
+    }
+
+    /// Called when `op` overflowed on two `Int`s. Without the `bigint` feature that's an error.
+    #[cfg(not(feature = "bigint"))]
+    fn overflowed(self, _: Value, _: Op) -> Result<Value, RollError> {
+        Err(RollError::Overflow)
+    }
+
+    /// Called when `op` overflowed on two `Int`s, which then widen to a `Big`.
+    #[cfg(feature = "bigint")]
+    fn overflowed(self, rhs: Value, op: Op) -> Result<Value, RollError> {
+        self.big_op(rhs, op)
+    }
+}
+
 impl Add for Value {
-    type Output = Self;
+    type Output = Result<Self, RollError>;
 
     fn add(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Float(i), Value::Float(j)) => Value::Float(i as f64 + j as f64),
-            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 + j as f64),
-            (Value::Float(i), Value::Int(j)) => Value::Float(i as f64 + j as f64),
-            (Value::Int(i), Value::Int(j)) => Value::Int(i + j),
-        }
+        Ok(match (self, rhs) {
+            (Value::Bool(b), _) | (_, Value::Bool(b)) => {
+                return Err(RollError::NotANumber(b.to_string()))
+            }
+            (Value::Float(i), Value::Float(j)) => Value::Float(i + j),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 + j),
+            (Value::Float(i), Value::Int(j)) => Value::Float(i + j as f64),
+            (Value::Int(i), Value::Int(j)) => match i.checked_add(j) {
+                Some(v) => Value::Int(v),
+                None => Value::Int(i).overflowed(Value::Int(j), Op::Add)?,
+            },
+            #[cfg(feature = "bigint")]
+            (l, r) => l.big_op(r, Op::Add)?,
+        })
     }
 }
 
 impl Sub for Value {
-    type Output = Self;
+    type Output = Result<Self, RollError>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Float(i), Value::Float(j)) => Value::Float(i as f64 - j),
-            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 - j as f64),
-            (Value::Float(i), Value::Int(j)) => Value::Float(i as f64 - j as f64),
-            (Value::Int(i), Value::Int(j)) => Value::Int(i - j),
-        }
+        Ok(match (self, rhs) {
+            (Value::Bool(b), _) | (_, Value::Bool(b)) => {
+                return Err(RollError::NotANumber(b.to_string()))
+            }
+            (Value::Float(i), Value::Float(j)) => Value::Float(i - j),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 - j),
+            (Value::Float(i), Value::Int(j)) => Value::Float(i - j as f64),
+            (Value::Int(i), Value::Int(j)) => match i.checked_sub(j) {
+                Some(v) => Value::Int(v),
+                None => Value::Int(i).overflowed(Value::Int(j), Op::Sub)?,
+            },
+            #[cfg(feature = "bigint")]
+            (l, r) => l.big_op(r, Op::Sub)?,
+        })
     }
 }
 
 impl Mul for Value {
-    type Output = Self;
+    type Output = Result<Self, RollError>;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Float(i), Value::Float(j)) => Value::Float(i as f64 * j as f64),
-            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 * j as f64),
-            (Value::Float(i), Value::Int(j)) => Value::Float(i as f64 * j as f64),
-            (Value::Int(i), Value::Int(j)) => Value::Int(i * j),
-        }
+        Ok(match (self, rhs) {
+            (Value::Bool(b), _) | (_, Value::Bool(b)) => {
+                return Err(RollError::NotANumber(b.to_string()))
+            }
+            (Value::Float(i), Value::Float(j)) => Value::Float(i * j),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 * j),
+            (Value::Float(i), Value::Int(j)) => Value::Float(i * j as f64),
+            (Value::Int(i), Value::Int(j)) => match i.checked_mul(j) {
+                Some(v) => Value::Int(v),
+                None => Value::Int(i).overflowed(Value::Int(j), Op::Mul)?,
+            },
+            #[cfg(feature = "bigint")]
+            (l, r) => l.big_op(r, Op::Mul)?,
+        })
     }
 }
 
 impl Div for Value {
-    type Output = Self;
+    type Output = Result<Self, RollError>;
 
     fn div(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Float(i), Value::Float(j)) => Value::Float(i as f64 / j as f64),
-            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 / j as f64),
-            (Value::Float(i), Value::Int(j)) => Value::Float(i as f64 / j as f64),
+        Ok(match (self, rhs) {
+            (Value::Bool(b), _) | (_, Value::Bool(b)) => {
+                return Err(RollError::NotANumber(b.to_string()))
+            }
+            (Value::Float(i), Value::Float(j)) => Value::Float(i / j),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 / j),
+            (Value::Float(i), Value::Int(j)) => Value::Float(i / j as f64),
             (Value::Int(i), Value::Int(j)) => Value::Float(i as f64 / j as f64),
-        }
+            #[cfg(feature = "bigint")]
+            (l, r) => Value::Float(f64::from(l) / f64::from(r)),
+        })
     }
 }
 
 impl Rem for Value {
-    type Output = Self;
+    type Output = Result<Self, RollError>;
 
     fn rem(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Float(i), Value::Float(j)) => Value::Float(i as f64 % j as f64),
-            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 % j as f64),
-            (Value::Float(i), Value::Int(j)) => Value::Float(i as f64 % j as f64),
-            (Value::Int(i), Value::Int(j)) => Value::Int(i % j),
-        }
+        Ok(match (self, rhs) {
+            (Value::Bool(b), _) | (_, Value::Bool(b)) => {
+                return Err(RollError::NotANumber(b.to_string()))
+            }
+            (Value::Float(i), Value::Float(j)) => Value::Float(i % j),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f64 % j),
+            (Value::Float(i), Value::Int(j)) => Value::Float(i % j as f64),
+            // Like the other divisions, dividing by zero isn't an error
+            (Value::Int(i), Value::Int(0)) => Value::Float(i as f64 % 0.0),
+            (Value::Int(i), Value::Int(j)) => Value::Int(i.wrapping_rem(j)),
+            #[cfg(feature = "bigint")]
+            (l, r) => l.big_op(r, Op::Rem)?,
+        })
     }
 }
 
 impl Neg for Value {
-    type Output = Self;
+    type Output = Result<Self, RollError>;
 
     fn neg(self) -> Self::Output {
-        match self {
+        Ok(match self {
+            Value::Bool(b) => return Err(RollError::NotANumber(b.to_string())),
             Value::Float(i) => Value::Float(-i),
-            Value::Int(i) => Value::Int(-i),
-        }
+            Value::Int(i) => match i.checked_neg() {
+                Some(v) => Value::Int(v),
+                None => Value::Int(0).overflowed(Value::Int(i), Op::Sub)?,
+            },
+            #[cfg(feature = "bigint")]
+            Value::Big(b) => Value::big(-b),
+        })
+    }
+}
+
+impl Not for Value {
+    type Output = Result<Self, RollError>;
+
+    fn not(self) -> Self::Output {
+        Ok(Value::Bool(!self.into_bool()?))
     }
 }
 
 impl Value {
+    /// Rounds down to an integer. Floats that don't fit in one, like infinity, stay floats.
     pub fn floor(self) -> Self {
+        self.round_with(f64::floor)
+    }
+
+    /// Rounds up to an integer, like `floor`.
+    pub fn ceil(self) -> Self {
+        self.round_with(f64::ceil)
+    }
+
+    /// Rounds to the nearest integer, and halfway away from zero, like `floor`.
+    pub fn round(self) -> Self {
+        self.round_with(f64::round)
+    }
+
+    fn round_with(self, round: fn(f64) -> f64) -> Self {
         match self {
-            Value::Float(i) => Value::Int(i.floor() as i64),
+            Value::Float(i) if (-(2f64.powi(63))..2f64.powi(63)).contains(&round(i)) => {
+                Value::Int(round(i) as i64)
+            }
+            #[cfg(feature = "bigint")]
+            Value::Float(i) if i.is_finite() => Value::big(BigInt::from_f64(round(i)).unwrap()),
+            Value::Float(i) => Value::Float(round(i)),
             i => i,
         }
     }
 
-    pub fn pow(self, rhs: Self) -> Self {
-        match (self, rhs) {
-            (Value::Float(i), Value::Float(j)) => Value::Float((i as f64).powf(j as f64)),
-            (Value::Int(i), Value::Float(j)) => Value::Float((i as f64).powf(j as f64)),
-            (Value::Float(i), Value::Int(j)) => Value::Float((i as f64).powf(j as f64)),
-            (Value::Int(i), Value::Int(j)) if j < 0 => Value::Float((i as f64).powf(j as f64)),
-            (Value::Int(i), Value::Int(j)) => Value::Int((i as i64).pow(j as u32)),
+    /// Compares two values, integers exactly and anything else as floats.
+    pub(crate) fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Int(i), Value::Int(j)) => Some(i.cmp(j)),
+            #[cfg(feature = "bigint")]
+            (Value::Big(_), Value::Int(_) | Value::Big(_)) | (Value::Int(_), Value::Big(_)) => {
+                self.to_big()?.partial_cmp(&other.to_big()?)
+            }
+            _ => f64::from(self.clone()).partial_cmp(&f64::from(other.clone())),
+        }
+    }
+
+    /// Compares two numbers, giving whether `test` holds for how they're ordered. Nothing
+    /// holds when one of them is NaN.
+    pub(crate) fn relation(
+        self,
+        rhs: Value,
+        test: fn(Ordering) -> bool,
+    ) -> Result<Value, RollError> {
+        if let (Value::Bool(b), _) | (_, Value::Bool(b)) = (&self, &rhs) {
+            return Err(RollError::NotANumber(b.to_string()));
         }
+
+        Ok(Value::Bool(self.compare(&rhs).is_some_and(test)))
+    }
+
+    pub(crate) fn into_bool(self) -> Result<bool, RollError> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            v => Err(RollError::NotABoolean(v.to_string())),
+        }
+    }
+
+    /// Divides and rounds down. Integers are divided exactly, instead of as floats.
+    pub fn idiv(self, rhs: Self) -> Result<Self, RollError> {
+        Ok(match (self, rhs) {
+            (Value::Int(i), Value::Int(j)) if j != 0 => match i.checked_div(j) {
+                // Dividing rounds towards zero, so a negative result is one too high
+                // when there's a remainder
+                Some(v) if i % j != 0 && (i < 0) != (j < 0) => Value::Int(v - 1),
+                Some(v) => Value::Int(v),
+                None => Value::Int(i).overflowed(Value::Int(j), Op::IDiv)?,
+            },
+            #[cfg(feature = "bigint")]
+            (l @ Value::Big(_), r @ (Value::Int(_) | Value::Big(_)))
+            | (l @ Value::Int(_), r @ Value::Big(_)) => l.big_op(r, Op::IDiv)?,
+            (l, r) => (l / r)?.floor(),
+        })
+    }
+
+    pub fn pow(self, rhs: Self) -> Result<Self, RollError> {
+        Ok(match (self, rhs) {
+            (Value::Bool(b), _) | (_, Value::Bool(b)) => {
+                return Err(RollError::NotANumber(b.to_string()))
+            }
+            (Value::Float(i), Value::Float(j)) => Value::Float(i.powf(j)),
+            (Value::Int(i), Value::Float(j)) => Value::Float((i as f64).powf(j)),
+            (Value::Float(i), Value::Int(j)) => Value::Float(i.powf(j as f64)),
+            (Value::Int(i), Value::Int(j)) if j < 0 => Value::Float((i as f64).powf(j as f64)),
+            (Value::Int(i), Value::Int(j)) => {
+                let e = match u32::try_from(j) {
+                    Ok(e) => e,
+                    // Only 0, 1 and -1 have powers this large that fit, and for those only
+                    // whether the power is odd matters
+                    Err(_) if (-1..=1).contains(&i) => (j % 2 + 2) as u32,
+                    Err(_) => u32::MAX,
+                };
+                match i.checked_pow(e) {
+                    Some(v) => Value::Int(v),
+                    None => Value::Int(i).overflowed(Value::Int(j), Op::Pow)?,
+                }
+            }
+            #[cfg(feature = "bigint")]
+            (l, r) => l.big_op(r, Op::Pow)?,
+        })
     }
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Ast {
-    Add(Box<Ast>, Box<Ast>),
-    Sub(Box<Ast>, Box<Ast>),
-    Mul(Box<Ast>, Box<Ast>),
-    Div(Box<Ast>, Box<Ast>),
-    Mod(Box<Ast>, Box<Ast>),
-    IDiv(Box<Ast>, Box<Ast>),
-    Power(Box<Ast>, Box<Ast>),
-    Minus(Box<Ast>),
+    Add(Box<Ast>, Box<Ast>, Span),
+    Sub(Box<Ast>, Box<Ast>, Span),
+    Mul(Box<Ast>, Box<Ast>, Span),
+    Div(Box<Ast>, Box<Ast>, Span),
+    Mod(Box<Ast>, Box<Ast>, Span),
+    IDiv(Box<Ast>, Box<Ast>, Span),
+    Power(Box<Ast>, Box<Ast>, Span),
+    Minus(Box<Ast>, Span),
+    Less(Box<Ast>, Box<Ast>, Span),
+    LessEqual(Box<Ast>, Box<Ast>, Span),
+    Equal(Box<Ast>, Box<Ast>, Span),
+    NotEqual(Box<Ast>, Box<Ast>, Span),
+    GreaterEqual(Box<Ast>, Box<Ast>, Span),
+    Greater(Box<Ast>, Box<Ast>, Span),
+    And(Box<Ast>, Box<Ast>, Span),
+    Or(Box<Ast>, Box<Ast>, Span),
+    Not(Box<Ast>, Span),
+    /// A condition, the expression used when it's true and the one used when it's false.
+    If(Box<Ast>, Box<Ast>, Box<Ast>, Span),
     Dice(
         Option<Box<Ast>>,
-        Option<Box<Ast>>,
+        Option<Sides<Box<Ast>>>,
         FilterModifier<Box<Ast>>,
-        u64,
+        ExplodeModifier<Box<Ast>>,
+        RerollModifier<Box<Ast>>,
+        SuccessModifier<Box<Ast>>,
+        Span,
     ),
 
-    Const(String),
+    Const(Value, Span),
+    Call(Function, Vec<Ast>, Span),
 }
 
-impl Display for Ast {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Ast {
+    /// Where this node is in the parsed expression.
+    pub fn span(&self) -> Span {
+        match self {
+            Ast::Add(_, _, span)
+            | Ast::Sub(_, _, span)
+            | Ast::Mul(_, _, span)
+            | Ast::Div(_, _, span)
+            | Ast::Mod(_, _, span)
+            | Ast::IDiv(_, _, span)
+            | Ast::Power(_, _, span)
+            | Ast::Minus(_, span)
+            | Ast::Less(_, _, span)
+            | Ast::LessEqual(_, _, span)
+            | Ast::Equal(_, _, span)
+            | Ast::NotEqual(_, _, span)
+            | Ast::GreaterEqual(_, _, span)
+            | Ast::Greater(_, _, span)
+            | Ast::And(_, _, span)
+            | Ast::Or(_, _, span)
+            | Ast::Not(_, span)
+            | Ast::If(_, _, _, span)
+            | Ast::Dice(_, _, _, _, _, _, span)
+            | Ast::Const(_, span)
+            | Ast::Call(_, _, span) => *span,
+        }
+    }
+
+    /// Whether this expression gives true or false instead of a number.
+    pub(crate) fn is_condition(&self) -> bool {
+        match self {
+            Ast::Less(..)
+            | Ast::LessEqual(..)
+            | Ast::Equal(..)
+            | Ast::NotEqual(..)
+            | Ast::GreaterEqual(..)
+            | Ast::Greater(..)
+            | Ast::And(..)
+            | Ast::Or(..)
+            | Ast::Not(..) => true,
+            // The parser checked both branches give the same kind of value
+            Ast::If(_, then, _, _) => then.is_condition(),
+            _ => false,
+        }
+    }
+}
+
+impl Ast {
+    /// Writes the expression like `Display` does, but writes every roll using `dice`. When
+    /// `branch` gives which branch of a conditional was taken, the other one is written as `_`.
+    pub(crate) fn fmt_with(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        dice: &dyn Fn(&Ast, &mut fmt::Formatter<'_>) -> fmt::Result,
+        branch: &dyn Fn(Span) -> Option<bool>,
+    ) -> fmt::Result {
+        let binary = |l: &Ast, op: &str, r: &Ast, f: &mut fmt::Formatter<'_>| {
+            l.fmt_with(f, dice, branch)?;
+            write!(f, " {} ", op)?;
+            r.fmt_with(f, dice, branch)
+        };
+
         match self {
-            Ast::Add(l, r) => {
-                l.fmt(f)?;
-                write!(f, " + ")?;
-                r.fmt(f)?;
+            Ast::Add(l, r, _) => binary(l, "+", r, f)?,
+            Ast::Sub(l, r, _) => binary(l, "-", r, f)?,
+            Ast::Mul(l, r, _) => binary(l, "*", r, f)?,
+            Ast::Div(l, r, _) => binary(l, "/", r, f)?,
+            Ast::Mod(l, r, _) => binary(l, "mod", r, f)?,
+            Ast::IDiv(l, r, _) => binary(l, "//", r, f)?,
+            Ast::Power(l, r, _) => binary(l, "**", r, f)?,
+            Ast::Minus(t, _) => {
+                write!(f, "-")?;
+                t.fmt_with(f, dice, branch)?;
             }
-            Ast::Sub(l, r) => {
-                l.fmt(f)?;
-                write!(f, " - ")?;
-                r.fmt(f)?;
+            Ast::Less(l, r, _) => binary(l, "<", r, f)?,
+            Ast::LessEqual(l, r, _) => binary(l, "<=", r, f)?,
+            Ast::Equal(l, r, _) => binary(l, "==", r, f)?,
+            Ast::NotEqual(l, r, _) => binary(l, "!=", r, f)?,
+            Ast::GreaterEqual(l, r, _) => binary(l, ">=", r, f)?,
+            Ast::Greater(l, r, _) => binary(l, ">", r, f)?,
+            Ast::And(l, r, _) => binary(l, "and", r, f)?,
+            Ast::Or(l, r, _) => binary(l, "or", r, f)?,
+            Ast::Not(t, _) => {
+                write!(f, "not ")?;
+                t.fmt_with(f, dice, branch)?;
             }
-            Ast::Mul(l, r) => {
-                l.fmt(f)?;
-                write!(f, " * ")?;
-                r.fmt(f)?;
+            Ast::If(c, then, otherwise, span) => {
+                let taken = branch(*span);
+                c.fmt_with(f, dice, branch)?;
+                write!(f, " ? ")?;
+                match taken {
+                    Some(false) => write!(f, "_")?,
+                    _ => then.fmt_with(f, dice, branch)?,
+                }
+                write!(f, " : ")?;
+                match taken {
+                    Some(true) => write!(f, "_")?,
+                    _ => otherwise.fmt_with(f, dice, branch)?,
+                }
             }
-            Ast::Div(l, r) => {
-                l.fmt(f)?;
-                write!(f, " / ")?;
-                r.fmt(f)?;
+            Ast::Dice(..) => dice(self, f)?,
+            Ast::Const(v, _) => v.fmt(f)?,
+            Ast::Call(function, args, _) => {
+                write!(f, "{}(", function)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    arg.fmt_with(f, dice, branch)?;
+                }
+                write!(f, ")")?;
             }
-            Ast::Mod(l, r) => {
-                l.fmt(f)?;
-                write!(f, " mod ")?;
-                r.fmt(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Ast {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(
+            f,
+            &|dice, f| {
+                if let Ast::Dice(times, sides, fm, em, rm, sm, _) = dice {
+                    if let Some(t) = times {
+                        t.fmt(f)?;
+                    }
+
+                    write!(f, "d")?;
+
+                    if let Some(s) = sides {
+                        s.fmt(f)?;
+                    }
+
+                    rm.fmt(f)?;
+                    em.fmt(f)?;
+                    fm.fmt(f)?;
+                    sm.fmt(f)?;
+                }
+
+                Ok(())
+            },
+            &|_| None,
+        )
+    }
+}
+
+impl Ast {
+    pub fn interp(self, rolls: &mut Vec<(Span, Roll)>) -> Result<Value, RollError> {
+        self.interp_with_rng(rolls, &mut OsRng)
+    }
+
+    /// Like `interp`, but rolls all dice using the given random number generator.
+    pub fn interp_with_rng(
+        self,
+        rolls: &mut Vec<(Span, Roll)>,
+        rng: &mut dyn RngCore,
+    ) -> Result<Value, RollError> {
+        self.interp_with_limits(rolls, rng, &Limits::DEFAULT)
+    }
+
+    /// Like `interp_with_rng`, but gives an error instead of doing more work than `limits`
+    /// allow.
+    pub fn interp_with_limits(
+        self,
+        rolls: &mut Vec<(Span, Roll)>,
+        rng: &mut dyn RngCore,
+        limits: &Limits,
+    ) -> Result<Value, RollError> {
+        self.interp_with_branches(rolls, &mut Vec::new(), rng, limits)
+    }
+
+    /// Like `interp_with_limits`, but also gives the number of dice that were rolled.
+    pub(crate) fn interp_counting_dice(
+        self,
+        rng: &mut dyn RngCore,
+        limits: &Limits,
+    ) -> Result<(Value, u64), RollError> {
+        let (mut rolls, mut branches) = (Vec::new(), Vec::new());
+        let mut interpreter = Interpreter {
+            rolls: &mut rolls,
+            branches: &mut branches,
+            rng,
+            limits,
+            dice: 0,
+        };
+        let value = interpreter.eval(self, 0)?;
+        Ok((value, interpreter.dice))
+    }
+
+    /// Like `interp_with_limits`, but also adds the span of every conditional to `branches`,
+    /// together with whether its condition was true.
+    pub(crate) fn interp_with_branches(
+        self,
+        rolls: &mut Vec<(Span, Roll)>,
+        branches: &mut Vec<(Span, bool)>,
+        rng: &mut dyn RngCore,
+        limits: &Limits,
+    ) -> Result<Value, RollError> {
+        Interpreter {
+            rolls,
+            branches,
+            rng,
+            limits,
+            dice: 0,
+        }
+        .eval(self, 0)
+    }
+}
+
+/// What's needed while evaluating an expression, and the dice rolled so far.
+struct Interpreter<'a> {
+    rolls: &'a mut Vec<(Span, Roll)>,
+    branches: &'a mut Vec<(Span, bool)>,
+    rng: &'a mut dyn RngCore,
+    limits: &'a Limits,
+    dice: u64,
+}
+
+impl Interpreter<'_> {
+    fn eval(&mut self, ast: Ast, depth: usize) -> Result<Value, RollError> {
+        if depth > self.limits.depth {
+            return Err(RollError::LimitExceeded(format!(
+                "nested operations, the limit is {}",
+                self.limits.depth
+            )));
+        }
+        let depth = depth + 1;
+
+        match ast {
+            Ast::Add(l, r, _) => self.binary(*l, *r, depth, |l, r| l + r),
+            Ast::Sub(l, r, _) => self.binary(*l, *r, depth, |l, r| l - r),
+            Ast::Div(l, r, _) => self.binary(*l, *r, depth, |l, r| l / r),
+            Ast::Mul(l, r, _) => self.binary(*l, *r, depth, |l, r| l * r),
+            Ast::Mod(l, r, _) => self.binary(*l, *r, depth, |l, r| l % r),
+            Ast::IDiv(l, r, _) => self.binary(*l, *r, depth, Value::idiv),
+            Ast::Power(l, r, _) => self.binary(*l, *r, depth, Value::pow),
+            Ast::Minus(l, _) => self.eval(*l, depth)?.neg(),
+            Ast::Less(l, r, _) => self.binary(*l, *r, depth, |l, r| l.relation(r, Ordering::is_lt)),
+            Ast::LessEqual(l, r, _) => {
+                self.binary(*l, *r, depth, |l, r| l.relation(r, Ordering::is_le))
             }
-            Ast::IDiv(l, r) => {
-                l.fmt(f)?;
-                write!(f, " // ")?;
-                r.fmt(f)?;
+            Ast::Equal(l, r, _) => {
+                self.binary(*l, *r, depth, |l, r| l.relation(r, Ordering::is_eq))
             }
-            Ast::Power(l, r) => {
-                l.fmt(f)?;
-                write!(f, " ** ")?;
-                r.fmt(f)?;
+            // Unlike the others, this holds when one of the numbers is NaN
+            Ast::NotEqual(l, r, _) => {
+                self.binary(*l, *r, depth, |l, r| l.relation(r, Ordering::is_eq)?.not())
             }
-            Ast::Minus(t) => {
-                write!(f, "-")?;
-                t.fmt(f)?;
+            Ast::GreaterEqual(l, r, _) => {
+                self.binary(*l, *r, depth, |l, r| l.relation(r, Ordering::is_ge))
             }
-            Ast::Dice(times, sides, fm, _) => {
-                if let Some(t) = times {
-                    t.fmt(f)?;
-                }
+            Ast::Greater(l, r, _) => {
+                self.binary(*l, *r, depth, |l, r| l.relation(r, Ordering::is_gt))
+            }
+            // Both sides are always evaluated, so all of their dice are rolled
+            Ast::And(l, r, _) => self.binary(*l, *r, depth, |l, r| {
+                Ok(Value::Bool(l.into_bool()? & r.into_bool()?))
+            }),
+            Ast::Or(l, r, _) => self.binary(*l, *r, depth, |l, r| {
+                Ok(Value::Bool(l.into_bool()? | r.into_bool()?))
+            }),
+            Ast::Not(l, _) => self.eval(*l, depth)?.not(),
+            Ast::If(c, then, otherwise, span) => self.branch(*c, *then, *otherwise, span, depth),
+            Ast::Const(val, _) => Ok(val),
+            Ast::Call(function, args, _) => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| self.eval(arg, depth))
+                    .collect::<Result<_, _>>()?;
+                function.apply(args)
+            }
+            Ast::Dice(..) => self.roll(ast, depth),
+        }
+    }
 
-                write!(f, "d")?;
+    /// Evaluates both sides of an operation, then applies it.
+    fn binary(
+        &mut self,
+        l: Ast,
+        r: Ast,
+        depth: usize,
+        op: fn(Value, Value) -> Result<Value, RollError>,
+    ) -> Result<Value, RollError> {
+        let l = self.eval(l, depth)?;
+        op(l, self.eval(r, depth)?)
+    }
 
-                if let Some(s) = sides {
-                    s.fmt(f)?;
-                }
+    /// Evaluates the condition of a conditional, and then only the branch it picks.
+    fn branch(
+        &mut self,
+        condition: Ast,
+        then: Ast,
+        otherwise: Ast,
+        span: Span,
+        depth: usize,
+    ) -> Result<Value, RollError> {
+        let taken = self.eval(condition, depth)?.into_bool()?;
+        self.branches.push((span, taken));
+        self.eval(if taken { then } else { otherwise }, depth)
+    }
 
-                fm.fmt(f)?;
-            }
-            Ast::Const(s) => f.write_str(s)?,
+    /// Adds to the number of dice rolled so far, which can't go over the limit.
+    fn count_dice(&mut self, dice: u64) -> Result<(), RollError> {
+        self.dice = self.dice.saturating_add(dice);
+        if self.dice > self.limits.total_dice {
+            return Err(RollError::LimitExceeded(format!(
+                "dice in total, the limit is {}",
+                self.limits.total_dice
+            )));
         }
-
         Ok(())
     }
-}
 
-impl Ast {
-    pub fn interp(self, rolls: &mut Vec<(u64, Roll)>) -> Result<Value, String> {
-        Ok(match self {
-            Ast::Add(l, r) => l.interp(rolls)? + r.interp(rolls)?,
-            Ast::Sub(l, r) => l.interp(rolls)? - r.interp(rolls)?,
-            Ast::Div(l, r) => l.interp(rolls)? / r.interp(rolls)?,
-            Ast::Mul(l, r) => l.interp(rolls)? * r.interp(rolls)?,
-            Ast::Mod(l, r) => l.interp(rolls)? % r.interp(rolls)?,
-            Ast::IDiv(l, r) => (l.interp(rolls)? / r.interp(rolls)?).floor(),
-            Ast::Power(l, r) => l.interp(rolls)?.pow(r.interp(rolls)?),
-            Ast::Minus(l) => -l.interp(rolls)?,
-            Ast::Const(val) => {
-                let dots = val.matches('.').count();
-                if dots == 0 {
-                    Value::Int(val.parse::<i64>().map_err(|e| e.to_string())?)
-                } else if dots == 1 {
-                    Value::Float(val.parse::<f64>().map_err(|e| e.to_string())?)
-                } else {
-                    return Err(format!(
-                        "{} couldn't be parsed as number (too many dots)",
-                        val
-                    ));
-                }
-            }
+    /// Rolls the dice of an `Ast::Dice`. This is kept out of `eval`, so the stack
+    /// doesn't grow as fast for deeply nested expressions.
+    fn roll(&mut self, dice: Ast, depth: usize) -> Result<Value, RollError> {
+        let (l, r, fm, em, rm, sm, dp) = match dice {
+            Ast::Dice(l, r, fm, em, rm, sm, dp) => (l, r, fm, em, rm, sm, dp),
+            _ => unreachable!(),
+        };
 
-            Ast::Dice(None, r, fm, dp) => {
-                Ast::Dice(Some(Box::new(Ast::Const("1".to_string()))), r, fm, dp).interp(rolls)?
-            }
-            Ast::Dice(l, None, fm, dp) => Ast::Dice(
-                l,
-                Some(Box::new(Ast::Const(DEFAULT_SIDES.to_string()))),
-                fm,
-                dp,
-            )
-            .interp(rolls)?,
-
-            Ast::Dice(Some(l), Some(r), fm, dp) => {
-                if let (Value::Int(lv), Value::Int(rv)) = (l.interp(rolls)?, r.interp(rolls)?) {
-                    let fm_value: FilterModifier<Value> = fm.map(|i| i.interp(rolls)).swap()?;
-
-                    let fm_int = fm_value
-                        .map(|i| {
-                            if let Value::Int(v) = i {
-                                Ok(v as u64)
-                            } else {
-                                Err(format!("{:?}: couldn't be parsed as int", i))
-                            }
-                        })
-                        .swap()?;
-
-                    let roll = roll_die(
-                        lv as u64,
-                        NonZeroU64::new(rv as u64).ok_or("Can't roll zero sided die")?,
-                        fm_int,
-                        rand_core::OsRng,
-                    );
-                    let total = roll.total;
-
-                    rolls.push((dp, roll));
-                    Value::Int(total)
-                } else {
-                    return Err("couldn't be parsed as dice roll (no ints)".to_string());
-                }
-            }
-        })
+        let to_int = |i| match i {
+            Value::Int(v) => Ok(v),
+            #[cfg(feature = "bigint")]
+            Value::Big(_) => Err(RollError::Overflow),
+            i => Err(RollError::NotAnInteger(i.to_string())),
+        };
+
+        let lv = match l {
+            Some(l) => to_int(self.eval(*l, depth)?)?,
+            None => 1,
+        };
+        if lv < 0 {
+            return Err(RollError::InvalidDiceCount(lv));
+        }
+        let r =
+            r.unwrap_or_else(|| Sides::Number(Box::new(Ast::Const(Value::Int(DEFAULT_SIDES), dp))));
+        let sides_value: Sides<Value> = r.map(|i| self.eval(*i, depth)).swap()?;
+        let sides = sides_value.map(to_int).swap()?;
+        sides.check()?;
+
+        let fm_value: FilterModifier<Value> = fm.map(|i| self.eval(*i, depth)).swap()?;
+        let fm_int = fm_value.map(to_int).swap()?.check()?;
+
+        let em_value: ExplodeModifier<Value> = em.map(|i| self.eval(*i, depth)).swap()?;
+        let em_int = em_value.map(to_int).swap()?;
+
+        let rm_value: RerollModifier<Value> = rm.map(|i| self.eval(*i, depth)).swap()?;
+        let rm_int = rm_value.map(to_int).swap()?;
+
+        let sm_value: SuccessModifier<Value> = sm.map(|i| self.eval(*i, depth)).swap()?;
+        let sm_int = sm_value.map(to_int).swap()?;
+
+        self.count_dice(lv as u64)?;
+
+        let mut roll = roll_die(
+            lv as u64,
+            sides,
+            fm_int,
+            em_int,
+            rm_int,
+            self.limits,
+            &mut *self.rng,
+        )?;
+        self.count_dice(roll.extra)?;
+        roll.count_successes(sm_int);
+        let total = roll.total;
+
+        self.rolls.push((dp, roll));
+        Ok(Value::Int(total))
     }
 }
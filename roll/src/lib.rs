@@ -1,14 +1,35 @@
+#[cfg(feature = "bigint")]
+mod bigint;
+mod compare;
+mod distribution;
+mod error;
+mod export;
 mod filtermodifier;
+mod functions;
 mod interpreter;
+mod limits;
 mod options;
 mod parser;
 mod roll;
+mod sides;
+mod simulation;
+mod span;
+mod svg;
 
+pub use crate::compare::Matchup;
+pub use crate::distribution::Distribution;
+pub use crate::error::RollError;
 use crate::interpreter::Ast;
+pub use crate::limits::Limits;
 pub use crate::parser::*;
 pub use crate::roll::*;
+pub use crate::sides::Sides;
+pub use crate::simulation::{Analysis, Method, Simulation, DEFAULT_SAMPLES};
+pub use crate::span::Span;
+pub use crate::svg::{svg_chart, Chart};
 use core::fmt;
 pub use rand_core;
+use rand_core::{OsRng, RngCore};
 use std::collections::HashMap;
 
 pub struct RollResult {
@@ -24,41 +45,124 @@ impl fmt::Display for RollResult {
 
 const STAT_ROLL: &str = "4d6l";
 pub fn roll_stats() -> String {
-    fn roll_stat() -> Roll {
+    roll_stats_with_rng(&mut OsRng)
+}
+
+/// Like `roll_stats`, but rolls using the given random number generator.
+pub fn roll_stats_with_rng(rng: &mut dyn RngCore) -> String {
+    fn roll_stat(rng: &mut dyn RngCore) -> Roll {
         let mut rolls = Vec::new();
         Parser::new(STAT_ROLL)
             .parse()
             .unwrap()
-            .interp(&mut rolls)
+            .interp_with_rng(&mut rolls, rng)
             .unwrap();
         rolls.remove(0).1
     }
     let mut res = String::new();
 
     for _ in 0..6 {
-        let roll = roll_stat();
+        let roll = roll_stat(rng);
         res.push_str(&format!("{:2}: {:?}\n", roll.total, roll.vals));
     }
     res
 }
 
-pub fn roll_inline(s: &str, advanced: bool) -> Result<RollResult, String> {
+pub fn roll_inline(s: &str, advanced: bool) -> Result<RollResult, RollError> {
+    roll_inline_with_rng(s, advanced, &mut OsRng)
+}
+
+/// Calculates the exact distribution of a dice expression, without rolling it.
+pub fn distribution(s: &str, advanced: bool) -> Result<Distribution, RollError> {
+    let mut p = Parser::new(s);
+    p.advanced = advanced;
+
+    p.parse()?.distribution(&Limits::DEFAULT)
+}
+
+/// Finds the distribution of a dice expression, either exactly or by simulating it
+/// using the given random number generator.
+pub fn analyze(
+    s: &str,
+    advanced: bool,
+    method: Method,
+    rng: &mut dyn RngCore,
+) -> Result<Analysis, RollError> {
+    analyze_with_limits(s, advanced, method, rng, &Limits::DEFAULT)
+}
+
+/// Like `analyze`, but gives an error instead of doing more work than `limits` allow.
+pub fn analyze_with_limits(
+    s: &str,
+    advanced: bool,
+    method: Method,
+    rng: &mut dyn RngCore,
+    limits: &Limits,
+) -> Result<Analysis, RollError> {
     let mut p = Parser::new(s);
     p.advanced = advanced;
+    p.max_depth = limits.depth;
+
+    p.parse()?.analyze(method, rng, limits)
+}
+
+/// Compares two dice expressions, either exactly or by simulating both using the given
+/// random number generator.
+pub fn compare(
+    a: &str,
+    b: &str,
+    advanced: bool,
+    method: Method,
+    rng: &mut dyn RngCore,
+) -> Result<Matchup, RollError> {
+    let parse = |s| {
+        let mut p = Parser::new(s);
+        p.advanced = advanced;
+        p.parse()
+    };
+
+    parse(a)?.compare(&parse(b)?, method, rng, &Limits::DEFAULT)
+}
+
+/// Like `roll_inline`, but rolls using the given random number generator.
+pub fn roll_inline_with_rng(
+    s: &str,
+    advanced: bool,
+    rng: &mut dyn RngCore,
+) -> Result<RollResult, RollError> {
+    roll_inline_with_limits(s, advanced, rng, &Limits::DEFAULT)
+}
 
-    let ast = p.parse().map_err(|e| e.to_string())?;
+/// Like `roll_inline_with_rng`, but gives an error instead of doing more work than `limits`
+/// allow.
+pub fn roll_inline_with_limits(
+    s: &str,
+    advanced: bool,
+    rng: &mut dyn RngCore,
+    limits: &Limits,
+) -> Result<RollResult, RollError> {
+    let mut p = Parser::new(s);
+    p.advanced = advanced;
+    p.max_depth = limits.depth;
 
-    let copy = ast.clone();
+    let ast = p.parse()?;
 
     let mut rolls = Vec::new();
-    let total = ast.interp(&mut rolls)?;
+    let mut branches = Vec::new();
+    let total = ast
+        .clone()
+        .interp_with_branches(&mut rolls, &mut branches, rng, limits)?;
 
     let mut map = HashMap::new();
-    for (pos, roll) in rolls {
-        map.insert(pos, roll);
+    for (span, roll) in rolls {
+        map.insert(span, roll);
     }
 
-    let res = replace_rolls(copy, &map, |roll| format!("{:?}", roll.vals));
+    let res = WithRolls {
+        ast: &ast,
+        rolls: &map,
+        branches: &branches.into_iter().collect(),
+    };
     let result: RollResult = RollResult {
         string_result: format!("{} = {} = {}", s, res, total),
         dice_total: total,
@@ -66,52 +170,34 @@ pub fn roll_inline(s: &str, advanced: bool) -> Result<RollResult, String> {
     Ok(result)
 }
 
-fn replace_rolls(ast: Ast, lookup: &HashMap<u64, Roll>, func: fn(&Roll) -> String) -> Ast {
-    return match ast {
-        Ast::Add(l, r) => Ast::Add(
-            Box::from(replace_rolls(*l, lookup, func)),
-            Box::from(replace_rolls(*r, lookup, func)),
-        ),
-        Ast::Sub(l, r) => Ast::Sub(
-            Box::from(replace_rolls(*l, lookup, func)),
-            Box::from(replace_rolls(*r, lookup, func)),
-        ),
-        Ast::Mul(l, r) => Ast::Mul(
-            Box::from(replace_rolls(*l, lookup, func)),
-            Box::from(replace_rolls(*r, lookup, func)),
-        ),
-        Ast::Div(l, r) => Ast::Div(
-            Box::from(replace_rolls(*l, lookup, func)),
-            Box::from(replace_rolls(*r, lookup, func)),
-        ),
-        Ast::Mod(l, r) => Ast::Mod(
-            Box::from(replace_rolls(*l, lookup, func)),
-            Box::from(replace_rolls(*r, lookup, func)),
-        ),
-        Ast::IDiv(l, r) => Ast::IDiv(
-            Box::from(replace_rolls(*l, lookup, func)),
-            Box::from(replace_rolls(*r, lookup, func)),
-        ),
-        Ast::Power(l, r) => Ast::Power(
-            Box::from(replace_rolls(*l, lookup, func)),
-            Box::from(replace_rolls(*r, lookup, func)),
-        ),
-        Ast::Minus(l) => Ast::Minus(Box::from(replace_rolls(*l, lookup, func))),
-        Ast::Dice(_, _, _, pos) => {
-            // Safety: we exhaustively add all positions to this hashmap so it must contain everything
-            // we look up.
-            let roll = lookup.get(&pos).unwrap();
-            Ast::Const(func(roll))
-        }
-        x @ Ast::Const(_) => x,
-    };
+/// Writes an expression with every roll replaced by its result. The branch of a conditional
+/// that wasn't taken, and so wasn't rolled, is left out.
+struct WithRolls<'a> {
+    ast: &'a Ast,
+    rolls: &'a HashMap<Span, Roll>,
+    branches: &'a HashMap<Span, bool>,
+}
+
+impl fmt::Display for WithRolls<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Safety: we exhaustively add all spans to this hashmap so it must contain everything
+        // we look up, except for the dice in branches that weren't taken, which aren't written.
+        self.ast
+            .fmt_with(f, &|dice, f| self.rolls[&dice.span()].fmt(f), &|span| {
+                self.branches.get(&span).copied()
+            })
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::interpreter::Value;
     use crate::parser::Parser;
+    use crate::roll::tests::{DeterministicRng, SeededRng};
+    use crate::roll::uniform;
     use bnf::Grammar;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
 
     const GRAMMAR: &str = include_str!("../../grammar.bnf");
 
@@ -132,15 +218,236 @@ mod test {
 
         for _ in 0..500 {
             let sentence = generate_sentence(&grammar);
-            if let Err(e) = Parser::new(&sentence).advanced().parse() {
-                println!("failed with sentence \"{}\" and error: {:?}", sentence, e);
-                break;
+            match Parser::new(&sentence).advanced().parse() {
+                // The grammar doesn't limit how many digits a number has
+                Err(RollError::Parse { message, .. })
+                    if message.contains("number is too large") => {}
+                Err(e) => panic!("failed with sentence \"{}\" and error: {:?}", sentence, e),
+                Ok(_) => {}
             }
         }
     }
 
+    /// Pieces of expressions, including the largest numbers and everything the parser treats
+    /// specially. Random strings of these should never make rolling panic. Large numbers are
+    /// in parentheses, so they can't end up as the number of dice to roll.
+    const PIECES: &[&str] = &[
+        "d",
+        "4d6",
+        "2d20",
+        "d%",
+        "dF",
+        "dF.1",
+        "d{1,2}",
+        "d{a,b}",
+        "d[-2..2]",
+        "d1!",
+        "d6!!",
+        "d6!p",
+        "r1",
+        "ro<3",
+        "ra6",
+        "kh",
+        "kl2",
+        "dl",
+        "dh9",
+        ">4",
+        "f1",
+        "+",
+        "-",
+        "*",
+        "/",
+        "//",
+        " mod ",
+        "**",
+        "(",
+        ")",
+        "0",
+        "1",
+        "2.5",
+        ".",
+        "1.2.3",
+        "é",
+        " ",
+        "(9223372036854775807)",
+        "(-9223372036854775807 - 1)",
+        "(99999999999999999999)",
+        "** (63)",
+        "** (64)",
+        "** (4294967297)",
+        "* -1",
+        "d9223372036854775807",
+        "d[-9223372036854775807..9223372036854775807]",
+        "999999999999d6",
+        "floor(",
+        "max(",
+        ",",
+        " > ",
+        " <= ",
+        " == ",
+        " != ",
+        " and ",
+        " or ",
+        "not ",
+        " ? ",
+        " : ",
+    ];
+
+    #[test]
+    fn fuzz_interp() {
+        let mut rng = SeededRng { state: 19 };
+
+        for _ in 0..10_000 {
+            let pieces = 1 + uniform(&mut rng, 8);
+            let s: String = (0..pieces)
+                .map(|_| PIECES[uniform(&mut rng, PIECES.len() as u64) as usize])
+                .collect();
+
+            // Errors are fine, only panics fail the test
+            let result = catch_unwind(AssertUnwindSafe(|| {
+                if let Ok(ast) = Parser::new(&s).parse() {
+                    let _ = ast.interp_with_rng(&mut Vec::new(), &mut rng);
+                }
+            }));
+            assert!(result.is_ok(), "\"{}\" panicked", s);
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limit = |s: &str, limits: &Limits| {
+            let res = roll_inline_with_limits(s, true, &mut DeterministicRng::new(), limits);
+            matches!(res, Err(RollError::LimitExceeded(_)))
+        };
+
+        assert!(limit("999999999999d6", &Limits::DEFAULT));
+        assert!(limit("d1000000000000", &Limits::DEFAULT));
+        // Every addition is nested in the one after it
+        let sum = |n| vec!["1"; n].join(" + ");
+        assert!(limit(&sum(300), &Limits::DEFAULT));
+        assert!(!limit(&sum(100), &Limits::DEFAULT));
+        let limits = Limits {
+            total_dice: 10,
+            ..Limits::DEFAULT
+        };
+        assert!(!limit("4d6 + 6d6", &limits));
+        assert!(limit("4d6 + 6d6 + d6", &limits));
+        assert!(limit("(4d6)d6", &limits));
+        // Explosions and rerolls that add a die count too
+        assert!(limit("d1!", &limits));
+        assert!(!limit("d1!", &Limits::DEFAULT));
+        assert!(limit("10d2ra<2", &limits));
+        assert!(!limit("5d2ra<2", &limits));
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let limit = |s: &str, limits: &Limits| {
+            let res = roll_inline_with_limits(s, true, &mut DeterministicRng::new(), limits);
+            matches!(res, Err(RollError::LimitExceeded(_)))
+        };
+
+        // Deeply nested expressions are rejected while parsing, before they overflow the
+        // stack. Debug builds use a lot more stack than release builds, so this gets as much
+        // as a main thread usually has, instead of the smaller stack of a test thread.
+        let test = move || {
+            let parens = |n| format!("{}1{}", "(".repeat(n), ")".repeat(n));
+            assert!(limit(&parens(100_000), &Limits::DEFAULT));
+            assert!(limit(&parens(201), &Limits::DEFAULT));
+            assert!(!limit(&parens(200), &Limits::DEFAULT));
+            let not = |n| format!("{}1 > 2", "not ".repeat(n));
+            assert!(limit(&not(100_000), &Limits::DEFAULT));
+            assert!(!limit(&not(100), &Limits::DEFAULT));
+
+            for nested in ["-(", "max(", "d(", "d6kh(", "(1 > 2 or ", "(1 > 2 ? 1 : "] {
+                let s = format!("{}1", nested.repeat(100_000));
+                assert!(limit(&s, &Limits::DEFAULT), "{}", nested);
+            }
+            assert!(limit(&"2 ** ".repeat(100_000), &Limits::DEFAULT));
+            assert!(limit(&"1 > 2 ? 1 : ".repeat(100_000), &Limits::DEFAULT));
+            assert!(limit(&vec!["1"; 100_000].join(" + "), &Limits::DEFAULT));
+
+            let shallow = Limits {
+                depth: 5,
+                ..Limits::DEFAULT
+            };
+            assert!(limit(&parens(6), &shallow));
+            assert!(!limit(&parens(5), &shallow));
+        };
+        std::thread::Builder::new()
+            .stack_size(8 << 20)
+            .spawn(test)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    #[test]
+    fn test_idiv() {
+        let eval = |s: &str| Parser::new(s).parse().unwrap().interp(&mut Vec::new());
+        assert_eq!(eval("7 // 2"), Ok(Value::Int(3)));
+        assert_eq!(eval("-7 // 2"), Ok(Value::Int(-4)));
+        assert_eq!(eval("7 // -2"), Ok(Value::Int(-4)));
+        assert_eq!(eval("-8 // -2"), Ok(Value::Int(4)));
+        // Too large to divide exactly as floats
+        assert_eq!(
+            eval("9007199254740993 // 1"),
+            Ok(Value::Int(9007199254740993))
+        );
+        assert_eq!(eval("7.5 // 2"), Ok(Value::Int(3)));
+        assert_eq!(eval("1 // 0"), Ok(Value::Float(f64::INFINITY)));
+    }
+
     #[test]
     fn test_inplace() {
         println!("{}", roll_inline("4d8 + 2d8", false).unwrap());
     }
+
+    #[test]
+    fn test_inplace_with_rng() {
+        // The 4d6 rolls 1 through 4 and shuffles them, then the last d6 rolls a 2
+        let res =
+            roll_inline_with_rng("4d6 + d6 * 10", false, &mut DeterministicRng::new()).unwrap();
+        assert_eq!(res.dice_total, Value::Int(10 + 2 * 10));
+    }
+
+    #[test]
+    fn test_inplace_branches() {
+        let roll = |s| {
+            roll_inline_with_rng(s, false, &mut DeterministicRng::new())
+                .unwrap()
+                .to_string()
+        };
+
+        // The d20 rolls a 1, so only the d4 is rolled
+        assert_eq!(
+            roll("d20 + 5 >= 15 ? 2d6 + 3 : d4"),
+            "d20 + 5 >= 15 ? 2d6 + 3 : d4 = [1] + 5 >= 15 ? _ : [2] = 2"
+        );
+        assert_eq!(
+            roll("d20 < 5 ? d6 + 3 : 2d4"),
+            "d20 < 5 ? d6 + 3 : 2d4 = [1] < 5 ? [2] + 3 : _ = 5"
+        );
+        assert_eq!(
+            roll("d6 > 3 or not d6 == 1"),
+            "d6 > 3 or not d6 == 1 = [1] > 3 or not [2] == 1 = true"
+        );
+    }
+
+    #[test]
+    fn test_inplace_reproducible() {
+        let a =
+            roll_inline_with_rng("(2d8)d6kh3 + d%", true, &mut DeterministicRng::new()).unwrap();
+        let b =
+            roll_inline_with_rng("(2d8)d6kh3 + d%", true, &mut DeterministicRng::new()).unwrap();
+        assert_eq!(a.to_string(), b.to_string());
+    }
+
+    #[test]
+    fn test_stats_with_rng() {
+        // Every stat is 4d6 with the lowest dropped, the rng rolls 1 through 4 for the first
+        let stats = roll_stats_with_rng(&mut DeterministicRng::new());
+        assert_eq!(stats.lines().count(), 6);
+        assert!(stats.starts_with(" 9: "));
+    }
 }
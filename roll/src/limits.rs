@@ -0,0 +1,60 @@
+use crate::error::RollError;
+use crate::sides::Sides;
+
+/// How much work rolling a single expression may take. Going over a limit gives
+/// `RollError::LimitExceeded`, except for explosions and rerolls, which just stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The most dice a single roll like `4d6` may roll, not counting explosions.
+    pub dice_per_roll: u64,
+    /// The most dice all rolls in the expression together may roll, counting the ones
+    /// explosions and `ra` rerolls add.
+    pub total_dice: u64,
+    /// The most faces a die may have.
+    pub sides: u64,
+    /// The most extra dice a single die may explode into. Without this a target
+    /// like `d6!>0` would keep rolling forever.
+    pub explosions: u64,
+    /// The most times a single die may be rerolled, for the same reason.
+    pub rerolls: u64,
+    /// How deeply operations may be nested. Parentheses count as a level of nesting too.
+    pub depth: usize,
+    /// The most dice a simulation may roll over all its rolls together. Every roll may
+    /// use its share of this, so a simulation that would go over it stops after one roll.
+    pub simulated_dice: u64,
+}
+
+impl Limits {
+    pub const DEFAULT: Limits = Limits {
+        dice_per_roll: 10_000,
+        total_dice: 100_000,
+        sides: 1_000_000_000,
+        explosions: 100,
+        rerolls: 100,
+        depth: 200,
+        simulated_dice: 100_000_000,
+    };
+
+    /// Checks a single roll of `times` dice with these sides stays within the limits.
+    pub(crate) fn check_roll(&self, times: u64, sides: &Sides<i64>) -> Result<(), RollError> {
+        if times > self.dice_per_roll {
+            return Err(RollError::LimitExceeded(format!(
+                "dice in one roll, the limit is {}",
+                self.dice_per_roll
+            )));
+        }
+        if sides.count() > self.sides {
+            return Err(RollError::LimitExceeded(format!(
+                "sides, the limit is {}",
+                self.sides
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -1,58 +1,188 @@
-use rand_core::OsRng;
-use roll_rs::{roll_direction, roll_inline, roll_stats, Parser};
-use std::{env, process};
+use rand_chacha::ChaCha20Rng;
+use rand_core::{OsRng, RngCore, SeedableRng};
+use roll_rs::{
+    analyze as analyze_with_rng, compare as compare_with_rng, roll_direction, roll_inline_with_rng,
+    roll_stats_with_rng, svg_chart, Analysis, Chart, Distribution, Method, Parser, Roll, Span,
+};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::{env, fs, process};
 
 fn main() {
     if env::args().len() <= 1 {
         print_usage()
     }
 
-    let mut argv: Vec<String> = env::args().skip(1).collect();
+    let mut advanced = false;
+    let mut short = false;
+    let mut seed = None;
+    let mut at_least = None;
+    let mut at_most = None;
+    let mut method = Method::Auto;
+    let mut format = Format::Text;
+    let mut svg: Option<String> = None;
+    let mut chart = Chart::Bar;
+
+    let mut argv = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(x) = args.next() {
+        // Options with a value can be written as `--seed 1234` or `--seed=1234`
+        let (name, value) = match x.split_once('=') {
+            Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+            _ => (x.as_str(), None),
+        };
+
+        match name {
+            "-a" | "--advanced" => advanced = true,
+            "-s" | "--short" => short = true,
+            "--seed" => seed = Some(parse_value(name, value.or_else(|| args.next()))),
+            "--at-least" => at_least = Some(parse_value(name, value.or_else(|| args.next()))),
+            "--at-most" => at_most = Some(parse_value(name, value.or_else(|| args.next()))),
+            "--format" => format = parse_value(name, value.or_else(|| args.next())),
+            "--svg" => svg = Some(parse_value(name, value.or_else(|| args.next()))),
+            "--chart" => chart = parse_value(name, value.or_else(|| args.next())),
+            "--exact" => method = Method::Exact,
+            "--simulate" => {
+                method = Method::Simulate(parse_value(name, value.or_else(|| args.next())))
+            }
+            _ => argv.push(x),
+        }
+    }
+
+    if argv.is_empty() {
+        print_usage()
+    }
+
+    // Without a seed pick a random one, it's printed so the roll can be replayed
+    let seed = seed.unwrap_or_else(|| OsRng.next_u64());
+    let mut rng = ChaCha20Rng::seed_from_u64(seed);
+
     match argv[0].as_str() {
-        "stats" => print_roll_stats(),
-        "dir" => print_roll_dir(),
-        "-h" | "--help" | "" => print_usage(),
-        _ => {
-            let mut advanced = false;
-            let mut short = false;
+        "stats" => print_roll_stats(&mut rng),
+        "dir" => print_roll_dir(&mut rng),
+        "analyze" => {
+            // Several dice codes can be analyzed at once, separated by "vs"
+            let codes: Vec<String> = argv[1..]
+                .split(|x| x == "vs")
+                .map(|x| x.join(" "))
+                .collect();
 
-            argv.retain(|x| match x.as_str() {
-                "-a" | "--advanced" => {
-                    advanced = true;
-                    false
+            let mut analyses = Vec::new();
+            for (index, s) in codes.iter().enumerate() {
+                let quick = at_least.is_some() || at_most.is_some();
+                if index != 0 && matches!(format, Format::Text) && !quick {
+                    println!();
                 }
-                "-s" | "--short" => {
-                    short = true;
-                    false
+                analyses.push(analyze(
+                    s, advanced, method, &format, at_least, at_most, &mut rng,
+                ));
+            }
+
+            if let Some(path) = svg {
+                let series: Vec<_> = codes
+                    .iter()
+                    .zip(&analyses)
+                    .map(|(s, a)| (s.as_str(), a.distribution()))
+                    .collect();
+                if let Err(e) = fs::write(&path, svg_chart(&series, chart)) {
+                    eprintln!("Couldn't write {}: {}", path, e);
+                    process::exit(1)
                 }
-                _ => true,
-            });
+            }
 
+            // Only simulations roll dice, so there's no seed to print otherwise
+            if !analyses.iter().any(|a| matches!(a, Analysis::Simulated(_))) {
+                return;
+            }
+        }
+        "compare" => {
+            // Either `roll compare 2d6 d12`, or with spaces `roll compare d20 + 7 vs d20 + 4`
+            let (a, b) = match argv.iter().position(|x| x == "vs") {
+                Some(i) => (argv[1..i].join(" "), argv[i + 1..].join(" ")),
+                None if argv.len() == 3 => (argv[1].clone(), argv[2].clone()),
+                None => {
+                    eprintln!(
+                        "Expected two dice codes, separated by \"vs\" if they contain spaces"
+                    );
+                    process::exit(1)
+                }
+            };
+            if !compare(&a, &b, advanced, method, &mut rng) {
+                return;
+            }
+        }
+        "-h" | "--help" | "" => print_usage(),
+        _ => {
             if short {
-                roll_short(&argv.join(" "), advanced);
+                roll_short(&argv.join(" "), advanced, &mut rng);
             } else {
-                roll_long(&argv.join(" "), advanced);
+                roll_long(&argv.join(" "), advanced, &mut rng);
             }
         }
     }
+
+    eprintln!("seed: {}", seed);
+}
+
+fn parse_value<T: FromStr<Err = impl Display>>(name: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+    match value.parse() {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("Invalid value for {}: \"{}\", {}", name, value, e);
+            process::exit(1)
+        }
+    }
+}
+
+/// How `analyze` prints a distribution.
+enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err("expected text, csv or json"),
+        }
+    }
 }
 
 fn print_usage() -> ! {
     println!("Syntax is: roll <dice_code>\nExample: roll 2d8 + 6 + d8");
     println!("Instead of a dice code you can also put \"stats\" or \"dir\" for a stats roll or direction roll respectively");
+    println!("Put \"analyze\" before a dice code to see the chance of every outcome instead of rolling it");
+    println!("Put \"compare\" before two dice codes to see which one rolls higher, like \"roll compare 2d6 vs d12\"");
     println!("\nArgs: ");
     println!("  -a: advanced mode (composite dice notation)");
     println!("  -s: smaller output");
+    println!("  --seed <n>: roll with the given seed, to replay an earlier roll");
+    println!("  --at-least <n>: only show the chance of getting n or more when analyzing");
+    println!("  --at-most <n>: only show the chance of getting n or less when analyzing");
+    println!("  --exact: only analyze when the exact distribution can be calculated");
+    println!(
+        "  --simulate <n>: analyze by rolling n times, instead of calculating the distribution"
+    );
+    println!("  --format <text|csv|json>: how to print the distribution when analyzing");
+    println!("  --svg <file>: also draw the distribution as a chart, \"vs\" draws several at once");
+    println!("  --chart <bar|line>: the kind of chart to draw");
     process::exit(0)
 }
 
-fn print_roll_dir() {
-    let dir = roll_direction(OsRng);
+fn print_roll_dir(rng: &mut ChaCha20Rng) {
+    let dir = roll_direction(rng);
     println!("{}", dir);
 }
 
-fn roll_short(s: &str, advanced: bool) {
-    match roll_inline(s, advanced) {
+fn roll_short(s: &str, advanced: bool, rng: &mut ChaCha20Rng) {
+    match roll_inline_with_rng(s, advanced, rng) {
         Ok(s) => println!("{}", s),
         Err(e) => {
             eprintln!("{}", e);
@@ -61,7 +191,7 @@ fn roll_short(s: &str, advanced: bool) {
     }
 }
 
-fn roll_long(s: &str, advanced: bool) {
+fn roll_long(s: &str, advanced: bool, rng: &mut ChaCha20Rng) {
     let mut p = Parser::new(s);
     p.advanced = advanced;
 
@@ -74,7 +204,7 @@ fn roll_long(s: &str, advanced: bool) {
     };
 
     let mut rolls = Vec::new();
-    let total = match ast.interp(&mut rolls) {
+    let total = match ast.interp_with_rng(&mut rolls, rng) {
         Ok(i) => i,
         Err(e) => {
             eprintln!("{}", e);
@@ -82,40 +212,283 @@ fn roll_long(s: &str, advanced: bool) {
         }
     };
 
+    print!("{}", long_layout(s, &total, rolls));
+}
+
+/// Writes an expression with its result, and every roll under where it's written in the
+/// expression.
+fn long_layout(s: &str, total: &dyn Display, mut rolls: Vec<(Span, Roll)>) -> String {
     rolls.sort_by_key(|i| i.0);
 
+    // Spans are in bytes, but the rolls are aligned to the characters of the expression
+    let rolls: Vec<(usize, Roll)> = rolls
+        .into_iter()
+        .map(|(span, roll)| (span.column(s), roll))
+        .collect();
+
     let mut header = String::new();
     for (x, roll) in &rolls {
-        for _ in header.len()..(*x as usize) {
-            header.push(' ');
-        }
+        pad(&mut header, *x);
         header.push_str(&format!("d{}", roll.sides));
     }
 
-    println!("{}", header);
-    println!("{} = {}", s, total);
-
+    let mut out = format!("{}\n{} = {}\n", header, s, total);
     let mut rows = Vec::new();
+    let mut notes = Vec::new();
+    let mut pool = false;
 
     for (x, roll) in rolls {
+        for (from, to) in &roll.rerolls {
+            notes.push(format!(
+                "d{} rerolled {} -> {}",
+                roll.sides,
+                roll.sides.display_face(*from),
+                roll.sides.display_face(*to)
+            ));
+        }
+
+        // Symbols don't add up to a total, so they're counted instead
+        let counts = roll.symbol_counts();
+        if !counts.is_empty() {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(symbol, n)| format!("{} {}", n, symbol))
+                .collect();
+            notes.push(format!("d{} rolled {}", roll.sides, counts.join(", ")));
+        }
+
         while roll.vals.len() > rows.len() {
             rows.push(String::new());
         }
 
-        for (index, val) in roll.vals.iter().enumerate() {
-            for _ in rows[index].len()..(x as usize) {
-                rows[index].push(' ');
-            }
+        pool |= !roll.outcomes.is_empty();
 
-            rows[index].push_str(&format!("{}", val));
+        for (index, row) in rows.iter_mut().enumerate().take(roll.vals.len()) {
+            pad(row, x);
+            row.push_str(&roll.display_val(index));
         }
     }
 
     for row in rows {
-        println!("{}", row);
+        out.push_str(&format!("{}\n", row));
+    }
+
+    if pool {
+        out.push_str("(* success, x failure)\n");
+    }
+
+    for note in notes {
+        out.push_str(&format!("{}\n", note));
+    }
+    out
+}
+
+/// Pads a line with spaces up to column `x`. A line that's already that long, like one
+/// with a long symbol, gets a single space so the next value doesn't run into it.
+fn pad(line: &mut String, x: usize) {
+    let len = line.chars().count();
+    if len >= x && len > 0 {
+        line.push(' ');
     }
+    for _ in len..x {
+        line.push(' ');
+    }
+}
+
+fn print_roll_stats(rng: &mut ChaCha20Rng) {
+    print!("{}", roll_stats_with_rng(rng));
+}
+
+/// The width of the longest bar in the histogram of `analyze`.
+const BAR_WIDTH: f64 = 50.0;
+
+/// Prints the distribution of an expression and returns it.
+fn analyze(
+    s: &str,
+    advanced: bool,
+    method: Method,
+    format: &Format,
+    at_least: Option<f64>,
+    at_most: Option<f64>,
+    rng: &mut ChaCha20Rng,
+) -> Analysis {
+    let analysis = match analyze_with_rng(s, advanced, method, rng) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2)
+        }
+    };
+    print_analysis(s, &analysis, format, at_least, at_most);
+    analysis
+}
+
+fn print_analysis(
+    s: &str,
+    analysis: &Analysis,
+    format: &Format,
+    at_least: Option<f64>,
+    at_most: Option<f64>,
+) {
+    let dist = analysis.distribution();
+    let sim = match analysis {
+        Analysis::Simulated(sim) => Some(sim),
+        Analysis::Exact(_) => None,
+    };
+
+    match format {
+        Format::Csv => {
+            print!("{}", analysis.to_csv());
+            return;
+        }
+        Format::Json => {
+            println!("{}", analysis.to_json());
+            return;
+        }
+        Format::Text => {}
+    }
+
+    // Simulated chances are followed by their 95% confidence interval
+    let interval = |(lo, hi): (f64, f64)| format!(" ({:.2}% to {:.2}%)", lo * 100.0, hi * 100.0);
+
+    if at_least.is_some() || at_most.is_some() {
+        if let Some(k) = at_least {
+            let range = sim.map(|sim| interval(sim.at_least_interval(k)));
+            let range = range.unwrap_or_default();
+            println!("{} >= {}: {:.2}%{}", s, k, dist.at_least(k) * 100.0, range);
+        }
+        if let Some(k) = at_most {
+            let range = sim.map(|sim| interval(sim.at_most_interval(k)));
+            let range = range.unwrap_or_default();
+            println!("{} <= {}: {:.2}%{}", s, k, dist.at_most(k) * 100.0, range);
+        }
+        return;
+    }
+
+    println!("{}", s);
+    let mean = match sim {
+        Some(sim) => {
+            println!("simulated {} rolls", sim.samples());
+            let (lo, hi) = sim.mean_interval();
+            format!("{:.2} ({:.2} to {:.2})", dist.mean(), lo, hi)
+        }
+        None => format!("{:.2}", dist.mean()),
+    };
+    println!(
+        "mean {}, std dev {:.2}, range {} to {}",
+        mean,
+        dist.std_dev(),
+        dist.min(),
+        dist.max()
+    );
+    println!();
+    print_histogram(dist);
 }
 
-fn print_roll_stats() {
-    print!("{}", roll_stats());
+/// Prints the chance of every outcome, the chance of that outcome or less, and a bar chart.
+fn print_histogram(dist: &Distribution) {
+    let width = dist
+        .outcomes()
+        .iter()
+        .map(|(v, _)| v.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("value".len());
+    let highest = dist.outcomes().iter().map(|(_, p)| *p).fold(0.0, f64::max);
+
+    println!("{:>width$}  {:>7}  {:>7}", "value", "chance", "at most");
+    let mut cumulative = 0.0;
+    for (v, p) in dist.outcomes() {
+        cumulative += p;
+        let bar = "#".repeat((p / highest * BAR_WIDTH).round() as usize);
+        println!(
+            "{:>width$}  {:>6.2}%  {:>6.2}%  {}",
+            v,
+            p * 100.0,
+            cumulative * 100.0,
+            bar
+        );
+    }
+}
+
+/// Prints how two expressions compare. Returns whether they were simulated, like `analyze`.
+fn compare(a: &str, b: &str, advanced: bool, method: Method, rng: &mut ChaCha20Rng) -> bool {
+    let matchup = match compare_with_rng(a, b, advanced, method, rng) {
+        Ok(i) => i,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2)
+        }
+    };
+
+    let sim = match &matchup.difference {
+        Analysis::Simulated(sim) => Some(sim),
+        Analysis::Exact(_) => None,
+    };
+    let chance = |p: f64| match sim {
+        Some(sim) => {
+            let (lo, hi) = sim.chance_interval(p);
+            format!(
+                "{:.2}% ({:.2}% to {:.2}%)",
+                p * 100.0,
+                lo * 100.0,
+                hi * 100.0
+            )
+        }
+        None => format!("{:.2}%", p * 100.0),
+    };
+
+    println!("{} vs {}", a, b);
+    if let Some(sim) = sim {
+        println!("simulated {} rolls", sim.samples());
+    }
+    println!("{} > {}: {}", a, b, chance(matchup.greater()));
+    println!("{} = {}: {}", a, b, chance(matchup.equal()));
+    println!("{} < {}: {}", a, b, chance(matchup.less()));
+    println!("mean difference {:.2}", matchup.mean_difference());
+
+    println!();
+    println!("{} - ({}):", a, b);
+    print_histogram(matchup.difference.distribution());
+
+    sim.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(s: &str) -> String {
+        let ast = Parser::new(s).parse().unwrap();
+        let mut rolls = Vec::new();
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        let total = ast.interp_with_rng(&mut rolls, &mut rng).unwrap();
+        long_layout(s, &total, rolls)
+    }
+
+    #[test]
+    fn test_layout_symbols() {
+        let out = layout("3d{hit,miss}");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[..2], ["d{hit,miss}", "3d{hit,miss} = 0"]);
+        assert!(lines[2..5].iter().all(|l| *l == "hit" || *l == "miss"));
+
+        // Symbols don't add up to the total, so they're counted
+        let counts = lines[5].strip_prefix("d{hit,miss} rolled ").unwrap();
+        let counted: usize = counts
+            .split(", ")
+            .map(|c| c.split(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(counted, 3);
+        assert_eq!(lines.len(), 6);
+    }
+
+    #[test]
+    fn test_layout_wide_values() {
+        // `d%` is written as `d100`, which is wider than the expression leaves room for
+        let out = layout("d%+d%");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "d100 d100");
+        assert_eq!(lines[2].split_whitespace().count(), 2);
+    }
 }
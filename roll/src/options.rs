@@ -1,21 +1,23 @@
-use core::fmt;
+use crate::error::RollError;
 use std::collections::HashSet;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct Options {
     options: HashSet<String>,
     lastpos: u64,
     messages: Vec<String>,
-    source: String,
+    /// Shared, because options are cloned all the time while parsing.
+    source: Rc<str>,
 }
 
 impl Options {
-    pub fn new(source: String) -> Self {
+    pub fn new(source: &str) -> Self {
         Self {
             options: HashSet::new(),
             lastpos: 0,
             messages: vec![],
-            source,
+            source: source.into(),
         }
     }
 
@@ -50,29 +52,16 @@ impl Options {
     }
 }
 
-impl fmt::Display for Options {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "{}", self.source)?;
-        writeln!(f, "{}^", " ".repeat(self.lastpos as usize))?;
+impl From<Options> for RollError {
+    fn from(options: Options) -> Self {
+        let mut expected: Vec<String> = options.options.into_iter().collect();
+        expected.sort();
 
-        if !self.options.is_empty() {
-            writeln!(f, "An error occurred: unexpected character.")?;
-            write!(f, "Expected any of: [")?;
-            for (index, i) in self.options.iter().enumerate() {
-                write!(f, "{}", i)?;
-
-                if index != self.options.len() - 1 {
-                    write!(f, ", ")?;
-                }
-            }
-            writeln!(f, "]")?;
-            writeln!(f)?;
-        }
-
-        for i in &self.messages {
-            writeln!(f, "{}", i)?;
+        RollError::Parse {
+            source: options.source.to_string(),
+            pos: options.lastpos,
+            expected,
+            message: options.messages.join("\n"),
         }
-
-        Ok(())
     }
 }
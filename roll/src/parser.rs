@@ -1,25 +1,112 @@
-use crate::filtermodifier::FilterModifier;
-use crate::interpreter::Ast;
+use crate::error::RollError;
+use crate::filtermodifier::{
+    Comparison, ExplodeModifier, FilterModifier, RerollModifier, SuccessModifier, Target,
+};
+use crate::functions::Function;
+use crate::interpreter::{Ast, Value};
+use crate::limits::Limits;
 use crate::options::Options;
+use crate::sides::Sides;
+use crate::span::Span;
 use std::iter::Peekable;
 use std::str::Chars;
 
+/// How tightly an operator binds, from loosest to tightest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Or,
+    And,
+    /// Comparisons, which bind more tightly than `not`.
+    Relation,
+    Sum,
+    Term,
+    /// A minus sign, which binds less tightly than `**` in `-2 ** 2`.
+    Factor,
+    Power,
+}
+
+impl Precedence {
+    /// What the right operand of an operator with this precedence is parsed with. `**` groups
+    /// to the right, and its exponent can be negative.
+    fn operand(self) -> Self {
+        match self {
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Relation,
+            Precedence::Relation => Precedence::Sum,
+            Precedence::Sum => Precedence::Term,
+            Precedence::Term | Precedence::Factor | Precedence::Power => Precedence::Factor,
+        }
+    }
+}
+
+/// A binary operator, how tightly it binds and the node it makes.
+type Operator = (
+    &'static str,
+    Precedence,
+    fn(Box<Ast>, Box<Ast>, Span) -> Ast,
+);
+
+/// Operators that start with another one come first.
+const OPERATORS: [Operator; 15] = [
+    ("or", Precedence::Or, Ast::Or),
+    ("and", Precedence::And, Ast::And),
+    ("<=", Precedence::Relation, Ast::LessEqual),
+    (">=", Precedence::Relation, Ast::GreaterEqual),
+    ("==", Precedence::Relation, Ast::Equal),
+    ("!=", Precedence::Relation, Ast::NotEqual),
+    ("<", Precedence::Relation, Ast::Less),
+    (">", Precedence::Relation, Ast::Greater),
+    ("+", Precedence::Sum, Ast::Add),
+    ("-", Precedence::Sum, Ast::Sub),
+    ("**", Precedence::Power, Ast::Power),
+    ("*", Precedence::Term, Ast::Mul),
+    ("//", Precedence::Term, Ast::IDiv),
+    ("/", Precedence::Term, Ast::Div),
+    ("mod", Precedence::Term, Ast::Mod),
+];
+
 #[derive(Debug)]
 pub struct Parser<'a> {
     expr: Peekable<Chars<'a>>,
     pos: u64,
-    source: String,
+    /// The byte offset of the next character.
+    offset: usize,
+    /// The byte offset just after the last character that isn't whitespace.
+    last: usize,
+    source: &'a str,
+    /// How deeply the expression is nested where the parser is.
+    depth: usize,
+    /// Set when the expression is nested more deeply than `max_depth`.
+    too_deep: bool,
 
     pub advanced: bool,
+    /// How deeply operations may be nested. Parsing an expression that's nested more deeply
+    /// gives `RollError::LimitExceeded`, instead of running out of stack.
+    pub max_depth: usize,
+}
+
+/// Where a parser is in the expression, so it can go back there after trying something.
+#[derive(Debug, Clone)]
+pub(crate) struct Cursor<'a> {
+    expr: Peekable<Chars<'a>>,
+    pos: u64,
+    offset: usize,
+    last: usize,
+    depth: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(expr: &'a str) -> Self {
         Self {
-            source: expr.to_string(),
+            source: expr,
             expr: expr.chars().peekable(),
             pos: 0,
+            offset: 0,
+            last: 0,
+            depth: 0,
+            too_deep: false,
             advanced: false,
+            max_depth: Limits::DEFAULT.depth,
         }
     }
 
@@ -28,30 +115,115 @@ impl<'a> Parser<'a> {
         self
     }
 
-    pub fn backup(&self) -> Self {
-        Self {
+    pub(crate) fn backup(&self) -> Cursor<'a> {
+        Cursor {
             expr: self.expr.clone(),
-            source: self.source.clone(),
             pos: self.pos,
-            advanced: self.advanced,
+            offset: self.offset,
+            last: self.last,
+            depth: self.depth,
+        }
+    }
+
+    pub(crate) fn restore(&mut self, cursor: Cursor<'a>) {
+        self.expr = cursor.expr;
+        self.pos = cursor.pos;
+        self.offset = cursor.offset;
+        self.last = cursor.last;
+        self.depth = cursor.depth;
+    }
+
+    /// Goes one level deeper into the expression. Past `max_depth` this fails, and makes
+    /// `parse` give `RollError::LimitExceeded`.
+    fn descend(&mut self) -> Result<(), Options> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            self.too_deep = true;
+            return Err(Options::new(self.source).pos(self.pos));
         }
+        Ok(())
     }
 
-    pub fn restore(&mut self, other: Self) {
-        self.expr = other.expr;
-        self.pos = other.pos;
-        self.source = other.source;
-        self.advanced = other.advanced;
+    /// Parses something nested one level deeper, like the expression between parentheses.
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, Options>,
+    ) -> Result<T, Options> {
+        self.descend()?;
+        let res = parse(self);
+        self.depth -= 1;
+        res
     }
 
     pub fn accept(&mut self, c: char, options: &Options) -> Result<(), Options> {
-        self.expect(c, &options)?;
+        self.expect(c, options)?;
 
-        self.pos += 1;
-        self.expr.next();
+        self.advance();
+        self.last = self.offset;
         Ok(())
     }
 
+    fn advance(&mut self) {
+        if let Some(c) = self.expr.next() {
+            self.pos += 1;
+            self.offset += c.len_utf8();
+        }
+    }
+
+    /// Skips whitespace and returns the offset of the node that starts here.
+    fn start(&mut self) -> usize {
+        self.skip_whitespace();
+        self.offset
+    }
+
+    /// The span from `start` up to the last character that was accepted.
+    fn span(&self, start: usize) -> Span {
+        Span::new(start, self.last)
+    }
+
+    /// A number that isn't written out, like the number of dice in `d6`.
+    fn implicit(&self, value: i64) -> Ast {
+        Ast::Const(Value::Int(value), Span::new(self.last, self.last))
+    }
+
+    /// Skips whitespace and checks whether there was any since the last character that was
+    /// accepted. Comparisons after a roll like `d6 > 3` need it, so they aren't read as
+    /// modifiers like the `>3` in `d6>3`.
+    fn after_whitespace(&mut self) -> bool {
+        self.skip_whitespace();
+        self.offset != self.last
+    }
+
+    /// Checks an expression gives a number, for the places where true or false can't be used.
+    fn number(&self, ast: Ast) -> Result<Ast, Options> {
+        if ast.is_condition() {
+            Err(self.kind_error(&ast, "this is true or false, but a number is needed here"))
+        } else {
+            Ok(ast)
+        }
+    }
+
+    /// Checks an expression gives true or false, like the operands of `and`.
+    fn condition(&self, ast: Ast) -> Result<Ast, Options> {
+        if ast.is_condition() {
+            Ok(ast)
+        } else {
+            Err(self.kind_error(&ast, "this is a number, but true or false is needed here"))
+        }
+    }
+
+    fn kind_error(&self, ast: &Ast, message: &str) -> Options {
+        Options::new(self.source)
+            .pos(ast.span().column(self.source) as u64)
+            .message(message)
+    }
+
+    /// Skips whitespace and checks whether a number starts here.
+    fn peek_digit(&mut self) -> bool {
+        self.skip_whitespace();
+        self.expr.peek().is_some_and(char::is_ascii_digit)
+    }
+
     pub fn accept_string(&mut self, text: &str, options: &Options) -> Result<(), Options> {
         let backup = self.backup();
         for c in text.chars() {
@@ -64,14 +236,17 @@ impl<'a> Parser<'a> {
         Ok(())
     }
 
-    pub fn expect(&mut self, c: char, options: &Options) -> Result<(), Options> {
+    pub fn skip_whitespace(&mut self) {
         while let Some(i) = self.expr.peek() {
             if !i.is_whitespace() {
                 break;
             }
-            self.pos += 1;
-            self.expr.next();
+            self.advance();
         }
+    }
+
+    pub fn expect(&mut self, c: char, options: &Options) -> Result<(), Options> {
+        self.skip_whitespace();
 
         let pk = self.expr.peek();
         if pk == Some(&c) {
@@ -105,281 +280,771 @@ impl<'a> Parser<'a> {
         Err(options.clone())
     }
 
-    pub fn parse(&mut self) -> Result<Ast, Options> {
-        let result = self.parse_expr(Options::new(self.source.clone()))?;
+    pub fn parse(&mut self) -> Result<Ast, RollError> {
+        let result = self.parse_expr(Options::new(self.source));
+        if self.too_deep {
+            return Err(RollError::LimitExceeded(format!(
+                "nested operations, the limit is {}",
+                self.max_depth
+            )));
+        }
+        let result = result?;
 
         if self.expr.next().is_some() {
-            return Err(Options::new(self.source.clone())
+            return Err(Options::new(self.source)
                 .pos(self.pos)
-                .message("unexpected trailing character(s)"));
+                .message("unexpected trailing character(s)")
+                .into());
         }
 
         Ok(result)
     }
 
     pub fn parse_expr(&mut self, options: Options) -> Result<Ast, Options> {
-        self.parse_sum(&options)
+        self.parse_conditional(&options)
     }
 
-    pub fn parse_sum(&mut self, options: &Options) -> Result<Ast, Options> {
-        let mut res = self.parse_term(options.clone())?;
+    /// Parses an expression that has to give a number, like the arguments of a function.
+    fn parse_numeric(&mut self, options: &Options) -> Result<Ast, Options> {
+        let ast = self.nested(|p| p.parse_conditional(options))?;
+        self.number(ast)
+    }
+
+    /// Parses a conditional like `d20 >= 15 ? 2d6 : 0`, or anything that binds more tightly.
+    pub fn parse_conditional(&mut self, options: &Options) -> Result<Ast, Options> {
+        let start = self.start();
+        let condition = self.parse_binary(Precedence::Or, options)?;
+        if self.accept('?', options).is_err() {
+            return Ok(condition);
+        }
+        let condition = self.condition(condition)?;
+        self.parse_branches(condition, start, options)
+    }
+
+    /// Parses the branches of a conditional after the `?`.
+    fn parse_branches(
+        &mut self,
+        condition: Ast,
+        start: usize,
+        options: &Options,
+    ) -> Result<Ast, Options> {
+        let then = self.nested(|p| p.parse_conditional(options))?;
+        self.accept(':', options)
+            .map_err(|e| e.message("expected `:` between the branches of a conditional"))?;
+        let otherwise = self.nested(|p| p.parse_conditional(options))?;
+
+        if then.is_condition() != otherwise.is_condition() {
+            return Err(self.kind_error(
+                &otherwise,
+                "both branches have to be numbers, or both have to be true or false",
+            ));
+        }
 
-        while let Ok(op) = self.accept_any(&['+', '-'], options.clone(), None) {
-            let right = self.parse_term(options.clone())?;
+        Ok(Ast::If(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+            self.span(start),
+        ))
+    }
 
-            res = match op {
-                '+' => Ast::Add(Box::new(res), Box::new(right)),
-                '-' => Ast::Sub(Box::new(res), Box::new(right)),
-                _ => unreachable!(),
+    /// Parses operations whose operators bind at least as tightly as `min`, like
+    /// `d20 + 5 >= 15`. This handles all binary operators in one loop, instead of a function
+    /// per precedence, so nesting an expression in parentheses doesn't take as much stack.
+    fn parse_binary(&mut self, min: Precedence, options: &Options) -> Result<Ast, Options> {
+        let start = self.start();
+        let mut res = self.parse_prefix(min, options)?;
+
+        // Every operation in a chain like `1 + 2 + 3` is nested in the next one
+        let depth = self.depth;
+        let mut max = Precedence::Power;
+        while let Some(operator) = self.parse_operator(min, max, options) {
+            self.descend()?;
+            let right = self.parse_binary(operator.1.operand(), options)?;
+            res = self.operation(operator, res, right, start)?;
+
+            // Comparisons can't be chained
+            if operator.1 == Precedence::Relation {
+                max = Precedence::And;
             }
         }
+        self.depth = depth;
 
         Ok(res)
     }
 
-    pub fn parse_term(&mut self, options: Options) -> Result<Ast, Options> {
-        let mut res = self.parse_factor(options.clone())?;
+    /// Parses `not` or a minus sign and what it applies to, if `min` allows them here, or
+    /// otherwise an atom.
+    fn parse_prefix(&mut self, min: Precedence, options: &Options) -> Result<Ast, Options> {
+        let start = self.start();
+        if min <= Precedence::Relation && self.accept_string("not", options).is_ok() {
+            return self.parse_not(start, options);
+        }
+        if min > Precedence::Factor {
+            return self.parse_atom(options.clone());
+        }
 
-        loop {
-            let mut options = options.clone();
-            let opres = self.accept_any(&['*', '/'], options.clone(), None);
-            let mut op = if let Ok(i) = opres {
-                i
-            } else if self.accept_string("mod", &options).is_ok() {
-                '%'
-            } else {
-                options.add_str("mod").add_str("//");
-                break;
-            };
+        match self.accept('-', options) {
+            Ok(_) => self.parse_minus(start, options),
+            Err(o) => self.parse_atom(o),
+        }
+    }
 
-            if op == '/' && self.accept('/', &options).is_ok() {
-                op = 'i'
-            } else {
-                options = options.add('/');
-            }
+    /// Parses what `not` applies to, which can be another `not` or a comparison.
+    fn parse_not(&mut self, start: usize, options: &Options) -> Result<Ast, Options> {
+        let value = self.nested(|p| p.parse_binary(Precedence::Relation, options))?;
+        Ok(Ast::Not(Box::new(self.condition(value)?), self.span(start)))
+    }
 
-            let right = self.parse_factor(options)?;
+    /// Parses what a minus sign applies to, which can be a power like in `-2 ** 2`.
+    fn parse_minus(&mut self, start: usize, options: &Options) -> Result<Ast, Options> {
+        let value = self.nested(|p| p.parse_binary(Precedence::Power, options))?;
+        Ok(Ast::Minus(Box::new(self.number(value)?), self.span(start)))
+    }
 
-            res = match op {
-                '*' => Ast::Mul(Box::new(res), Box::new(right)),
-                '/' => Ast::Div(Box::new(res), Box::new(right)),
-                'i' => Ast::IDiv(Box::new(res), Box::new(right)),
-                '%' => Ast::Mod(Box::new(res), Box::new(right)),
-                _ => unreachable!(),
-            }
+    /// Parses a binary operator that binds at least as tightly as `min` and at most as
+    /// tightly as `max`, if there's one here.
+    fn parse_operator(
+        &mut self,
+        min: Precedence,
+        max: Precedence,
+        options: &Options,
+    ) -> Option<Operator> {
+        let backup = self.backup();
+        let &operator = OPERATORS
+            .iter()
+            .find(|(text, _, _)| self.accept_string(text, options).is_ok())?;
+
+        if (min..=max).contains(&operator.1) {
+            Some(operator)
+        } else {
+            self.restore(backup);
+            None
         }
+    }
 
-        Ok(res)
+    /// Makes the node of a binary operation, after checking its operands give the kind of
+    /// value the operator needs.
+    fn operation(
+        &self,
+        (_, precedence, operation): Operator,
+        left: Ast,
+        right: Ast,
+        start: usize,
+    ) -> Result<Ast, Options> {
+        let check = if precedence <= Precedence::And {
+            Self::condition
+        } else {
+            Self::number
+        };
+
+        Ok(operation(
+            Box::new(check(self, left)?),
+            Box::new(check(self, right)?),
+            self.span(start),
+        ))
     }
 
-    pub fn parse_factor(&mut self, options: Options) -> Result<Ast, Options> {
-        let backup = self.backup();
+    pub fn parse_sum(&mut self, options: &Options) -> Result<Ast, Options> {
+        self.parse_binary(Precedence::Sum, options)
+    }
 
-        Ok(match self.accept('-', &options) {
-            Ok(_) => Ast::Minus(Box::new(self.parse_power(options)?)),
-            Err(o) => {
-                self.restore(backup);
+    pub fn parse_term(&mut self, options: Options) -> Result<Ast, Options> {
+        self.parse_binary(Precedence::Term, &options)
+    }
 
-                return self.parse_power(o);
-            }
-        })
+    pub fn parse_factor(&mut self, options: Options) -> Result<Ast, Options> {
+        self.parse_binary(Precedence::Factor, &options)
     }
 
     pub fn parse_power(&mut self, options: Options) -> Result<Ast, Options> {
-        let mut res = self.parse_atom(options.clone())?;
-        if self.accept_string("**", &options).is_ok() {
-            let right = self.parse_factor(options)?;
-            res = Ast::Power(Box::new(res), Box::new(right));
+        self.parse_binary(Precedence::Power, &options)
+    }
+
+    pub fn parse_atom(&mut self, options: Options) -> Result<Ast, Options> {
+        let start = self.start();
+        if self.accept('(', &options).is_err() {
+            return self.parse_operand(options);
         }
 
-        Ok(res)
+        let sm = self.nested(|p| p.parse_conditional(&options))?;
+        self.accept(')', &options)
+            .map_err(|e| e.message("missing closing parenthesis"))?;
+
+        // In advanced mode this can be the number of dice, like in `(d4)d6`. Checking
+        // that here means the expression never has to be parsed twice.
+        if self.advanced && self.expect('d', &options).is_ok() {
+            let rolls = Box::new(self.number(sm)?);
+            return self.parse_roll(Some(rolls), start, options);
+        }
+        Ok(sm)
     }
 
-    pub fn parse_atom(&mut self, options: Options) -> Result<Ast, Options> {
+    /// Parses an atom that isn't between parentheses: a call, a roll or a number.
+    fn parse_operand(&mut self, options: Options) -> Result<Ast, Options> {
+        if let Some(call) = self.parse_call(&options)? {
+            return Ok(call);
+        }
+
+        // Once the `d` comes this can only be a roll, so its error is the one to report
+        let roll = self.expr.clone().find(|c| !c.is_ascii_digit() && *c != '_') == Some('d');
         let backup = self.backup();
         Ok(match self.parse_dice(options) {
-            Err(mut o) => {
+            Err(o) if roll => return Err(o),
+            Err(o) => {
                 self.restore(backup);
 
-                let backup = self.backup();
-                if self.accept('(', &o).is_ok() {
-                    let sm = self.parse_sum(&o)?;
-                    self.accept(')', &o)
-                        .map_err(|e| e.message("missing closing parenthesis"))?;
-
-                    return Ok(sm);
-                }
-                o = o
+                let o = o
                     .add('(')
                     .message("tried to parse expression between parenthesis");
-                self.restore(backup);
-
                 self.parse_number(&o.message("tried to parse dice roll"))?
             }
             Ok(i) => i,
         })
     }
 
-    pub fn parse_dice(&mut self, mut options: Options) -> Result<Ast, Options> {
+    /// Parses a call of a built-in function like `max(1, d4 - 2)`, if one starts here.
+    pub fn parse_call(&mut self, options: &Options) -> Result<Option<Ast>, Options> {
         let backup = self.backup();
+        let start = self.start();
+
+        let function = self
+            .parse_symbol(options)
+            .ok()
+            .and_then(|name| Function::from_name(&name));
+        let function = match function {
+            Some(function) if self.accept('(', options).is_ok() => function,
+            _ => {
+                self.restore(backup);
+                return Ok(None);
+            }
+        };
 
-        let rolls = if self.advanced && self.accept('(', &options).is_ok() {
-            let sm = self.parse_sum(&options)?;
-            self.accept(')', &options)
-                .map_err(|e| e.message("missing closing parenthesis"))?;
+        let mut args = Vec::new();
+        if self.expect(')', options).is_err() {
+            loop {
+                args.push(self.parse_numeric(options)?);
+                if self.accept(',', options).is_err() {
+                    break;
+                }
+            }
+        }
+        self.accept(')', &options.clone().add(','))
+            .map_err(|e| e.message("missing closing parenthesis"))?;
+
+        function
+            .check_arguments(args.len())
+            .map_err(|message| Options::new(self.source).pos(self.pos).message(message))?;
+
+        Ok(Some(Ast::Call(function, args, self.span(start))))
+    }
 
-            Some(Box::new(sm))
+    /// Parses a roll like `4d6kh3`. In advanced mode, a number of dice between parentheses is
+    /// parsed by `parse_atom`.
+    pub fn parse_dice(&mut self, options: Options) -> Result<Ast, Options> {
+        let start = self.start();
+        let rolls = if self.peek_digit() {
+            Some(Box::new(self.parse_number(&options)?))
         } else {
-            if self.advanced {
-                options = options
-                    .add('(')
-                    .message("tried to parse expression between parenthesis");
-            }
-            self.restore(backup);
-            self.parse_number(&options).map(Box::new).ok()
+            None
         };
 
+        self.parse_roll(rolls, start, options)
+    }
+
+    /// Parses the rest of a roll that starts at `start`, from the `d` after the number of dice.
+    /// Every part of the roll is parsed by its own function, so the stack doesn't grow as fast
+    /// for rolls nested in the sides of other rolls.
+    fn parse_roll(
+        &mut self,
+        rolls: Option<Box<Ast>>,
+        start: usize,
+        mut options: Options,
+    ) -> Result<Ast, Options> {
         self.accept('d', &options)?;
-        let dpos = self.pos - 1;
 
+        let sides = self.parse_sides(&mut options)?;
+        let rm = self.parse_reroll(&options)?;
+        let pos = self.pos;
+        let em = self.parse_explode(&options)?;
+        // Symbols have no value to add to or lower, they can only explode into more dice
+        if matches!(sides, Some(Sides::Symbols(_)))
+            && matches!(
+                em,
+                ExplodeModifier::Compound(_) | ExplodeModifier::Penetrate(_)
+            )
+        {
+            return Err(Options::new(self.source)
+                .pos(pos)
+                .message("dice with symbols can only explode with `!`"));
+        }
+        let fm = self.parse_filter(&options)?;
+        let sm = self.parse_success(&options)?;
+
+        Ok(Ast::Dice(rolls, sides, fm, em, rm, sm, self.span(start)))
+    }
+
+    /// Parses the sides of a roll after the `d`, if there are any.
+    fn parse_sides(&mut self, options: &mut Options) -> Result<Option<Sides<Box<Ast>>>, Options> {
         let backup = self.backup();
-        let sides = if self.advanced && self.accept('(', &options).is_ok() {
-            let sm = self.parse_sum(&options)?;
-            self.accept(')', &options)
+        if self.advanced && self.accept('(', options).is_ok() {
+            let sm = self.parse_numeric(options)?;
+            self.accept(')', options)
                 .map_err(|e| e.message("missing closing parenthesis"))?;
 
-            Some(Box::new(sm))
-        } else {
-            if self.advanced {
-                options = options
-                    .add('(')
-                    .message("tried to parse expression between parenthesis");
+            return Ok(Some(Sides::Number(Box::new(sm))));
+        }
+
+        if self.advanced {
+            *options = options
+                .clone()
+                .add('(')
+                .message("tried to parse expression between parenthesis");
+        }
+        self.restore(backup);
+
+        Ok(if self.accept('F', options).is_ok() {
+            if self.accept_string(".1", options).is_ok() {
+                Some(Sides::Fudge1)
+            } else {
+                Some(Sides::Fudge)
             }
-            self.restore(backup);
+        } else if self.accept('{', options).is_ok() {
+            Some(self.parse_faces(options)?)
+        } else if self.accept('[', options).is_ok() {
+            Some(self.parse_range(options)?)
+        } else if self.peek_digit() || self.expect('%', options).is_ok() {
+            let n = self.parse_number_or_percent(options.clone())?;
+            Some(Sides::Number(Box::new(n)))
+        } else {
+            None
+        })
+    }
 
-            self.parse_number_or_percent(options.clone())
-                .map(Box::new)
-                .ok()
-        };
+    fn parse_reroll(&mut self, options: &Options) -> Result<RerollModifier<Box<Ast>>, Options> {
+        if self.accept('r', options).is_err() {
+            return Ok(RerollModifier::None);
+        }
 
-        let fm = if self.accept_string("kh", &options).is_ok() || self.accept('h', &options).is_ok()
-        {
-            FilterModifier::KeepHighest(Box::new(
-                self.parse_number(&options)
-                    .unwrap_or_else(|_| Ast::Const("1".to_string())),
-            ))
-        } else if self.accept_string("dl", &options).is_ok() || self.accept('l', &options).is_ok() {
-            FilterModifier::DropLowest(Box::new(
-                self.parse_number(&options)
-                    .unwrap_or_else(|_| Ast::Const("1".to_string())),
-            ))
-        } else if self.accept_string("dh", &options).is_ok() {
-            FilterModifier::DropHighest(Box::new(
-                self.parse_number(&options)
-                    .unwrap_or_else(|_| Ast::Const("1".to_string())),
-            ))
-        } else if self.accept_string("kl", &options).is_ok() {
-            FilterModifier::KeepLowest(Box::new(
-                self.parse_number(&options)
-                    .unwrap_or_else(|_| Ast::Const("1".to_string())),
-            ))
+        let kind = if self.accept('o', options).is_ok() {
+            RerollModifier::RerollOnce
+        } else if self.accept('a', options).is_ok() {
+            RerollModifier::RerollAdd
         } else {
-            FilterModifier::None
+            RerollModifier::Reroll
         };
 
-        Ok(Ast::Dice(rolls, sides, fm, dpos))
+        let target = self.parse_target(options)?.ok_or_else(|| {
+            options
+                .clone()
+                .pos(self.pos)
+                .add_str("0-9")
+                .message("expected a reroll target")
+        })?;
+        Ok(kind(target))
     }
 
-    pub fn parse_number_or_percent(&mut self, options: Options) -> Result<Ast, Options> {
-        if self.accept('%', &options).is_ok() {
-            Ok(Ast::Const("100".to_ascii_lowercase()))
+    fn parse_explode(&mut self, options: &Options) -> Result<ExplodeModifier<Box<Ast>>, Options> {
+        if self.after_whitespace() || self.accept('!', options).is_err() {
+            return Ok(ExplodeModifier::None);
+        }
+
+        // The `!` in `d6! != 3` is an explosion, not the start of `!!`
+        let kind = if self.after_whitespace() {
+            ExplodeModifier::Explode
+        } else if self.accept('!', options).is_ok() {
+            ExplodeModifier::Compound
+        } else if self.accept('p', options).is_ok() {
+            ExplodeModifier::Penetrate
         } else {
-            self.parse_number(&options.add('%'))
+            ExplodeModifier::Explode
+        };
+
+        // Only a comparison that counts successes can have a failure target, so in `d6!>4f1`
+        // the `>4` isn't where the die explodes
+        let backup = self.backup();
+        let target = self.parse_target(options)?;
+        if target.is_some() && self.expect('f', options).is_ok() {
+            self.restore(backup);
+            return Ok(kind(None));
         }
+        Ok(kind(target))
     }
 
-    pub fn parse_number(&mut self, options: &Options) -> Result<Ast, Options> {
-        const DIGITS: &[char] = &['1', '2', '3', '4', '5', '6', '7', '8', '9', '0', '.'];
-        let digits_name = Options::new("".to_string()).add_str("0-9");
+    fn parse_filter(&mut self, options: &Options) -> Result<FilterModifier<Box<Ast>>, Options> {
+        Ok(
+            if self.accept_string("kh", options).is_ok() || self.accept('h', options).is_ok() {
+                FilterModifier::KeepHighest(Box::new(self.parse_filter_count(options)?))
+            } else if self.accept_string("dl", options).is_ok() || self.accept('l', options).is_ok()
+            {
+                FilterModifier::DropLowest(Box::new(self.parse_filter_count(options)?))
+            } else if self.accept_string("dh", options).is_ok() {
+                FilterModifier::DropHighest(Box::new(self.parse_filter_count(options)?))
+            } else if self.accept_string("kl", options).is_ok() {
+                FilterModifier::KeepLowest(Box::new(self.parse_filter_count(options)?))
+            } else {
+                FilterModifier::None
+            },
+        )
+    }
+
+    fn parse_success(&mut self, options: &Options) -> Result<SuccessModifier<Box<Ast>>, Options> {
+        let cmp = match self.parse_comparison(options) {
+            Some(cmp) => cmp,
+            None => return Ok(SuccessModifier::None),
+        };
 
-        let mut number = vec![self
-            .accept_any(DIGITS, options.clone(), Some(digits_name.clone()))
-            .map_err(|e| {
+        let success = Target {
+            cmp,
+            value: Box::new(
+                self.parse_number(options)
+                    .map_err(|e| e.message("expected a number to count successes with"))?,
+            ),
+        };
+
+        let failure = if self.accept('f', options).is_ok() {
+            Some(self.parse_target(options)?.ok_or_else(|| {
                 options
                     .clone()
-                    .merge(e)
-                    .add('(')
-                    .message("tried to parse a number")
-            })?];
+                    .pos(self.pos)
+                    .add_str("0-9")
+                    .message("expected a failure target")
+            })?)
+        } else {
+            None
+        };
 
-        loop {
+        Ok(SuccessModifier::Count(success, failure))
+    }
+
+    /// The number of dice to keep or drop, which is 1 when it's left out. In advanced mode
+    /// this can be an expression between parentheses.
+    fn parse_filter_count(&mut self, options: &Options) -> Result<Ast, Options> {
+        let backup = self.backup();
+        if self.advanced && self.accept('(', options).is_ok() {
+            let sm = self.parse_numeric(options)?;
+            self.accept(')', options)
+                .map_err(|e| e.message("missing closing parenthesis"))?;
+
+            return Ok(sm);
+        }
+        self.restore(backup);
+
+        if self.peek_digit() {
+            self.parse_number(options)
+        } else {
+            Ok(self.implicit(1))
+        }
+    }
+
+    /// Parses the comparison of a modifier like the `>` in `d6>3`, which has to come right
+    /// after the rest of the roll.
+    pub fn parse_comparison(&mut self, options: &Options) -> Option<Comparison> {
+        if self.after_whitespace() {
+            None
+        } else if self.accept_string(">=", options).is_ok() {
+            Some(Comparison::GreaterEqual)
+        } else if self.accept_string("<=", options).is_ok() {
+            Some(Comparison::LessEqual)
+        } else if self.accept('>', options).is_ok() {
+            Some(Comparison::Greater)
+        } else if self.accept('<', options).is_ok() {
+            Some(Comparison::Less)
+        } else if self.accept('=', options).is_ok() {
+            Some(Comparison::Equal)
+        } else {
+            None
+        }
+    }
+
+    /// Parses an optional comparison target like `>8` or `3`. A bare number means "equal to".
+    pub fn parse_target(&mut self, options: &Options) -> Result<Option<Target<Box<Ast>>>, Options> {
+        let cmp = if let Some(cmp) = self.parse_comparison(options) {
+            cmp
+        } else {
             let backup = self.backup();
-            if let Ok(digit) = self.accept_any(DIGITS, options.clone(), Some(digits_name.clone())) {
-                number.push(digit);
-            } else {
+            if !self.peek_digit() {
                 self.restore(backup);
+                return Ok(None);
+            }
+
+            return Ok(Some(Target {
+                cmp: Comparison::Equal,
+                value: Box::new(self.parse_number(options)?),
+            }));
+        };
+
+        let value = self
+            .parse_number(options)
+            .map_err(|e| e.message("expected a number to compare against"))?;
+
+        Ok(Some(Target {
+            cmp,
+            value: Box::new(value),
+        }))
+    }
+
+    /// Parses a list of faces after the opening `{`, either all numbers or all symbols.
+    pub fn parse_faces(&mut self, options: &Options) -> Result<Sides<Box<Ast>>, Options> {
+        let mut numbers = Vec::new();
+        let mut symbols = Vec::new();
+
+        loop {
+            let start = self.start();
+            if let Ok(symbol) = self.parse_symbol(options) {
+                symbols.push(symbol);
+            } else if self.accept('-', options).is_ok() {
+                let value = self.parse_number(options)?;
+                numbers.push(Box::new(Ast::Minus(Box::new(value), self.span(start))));
+            } else {
+                numbers.push(Box::new(
+                    self.parse_number(&options.clone().add('-').add_str("a-z"))?,
+                ));
+            }
+
+            if !numbers.is_empty() && !symbols.is_empty() {
+                return Err(options
+                    .clone()
+                    .pos(self.pos)
+                    .message("a die can't have both numbers and symbols on its faces"));
+            }
+
+            if self.accept(',', options).is_err() {
                 break;
             }
         }
 
-        let string: String = number.iter().collect();
+        self.accept('}', &options.clone().add(','))
+            .map_err(|e| e.message("missing closing brace"))?;
 
-        Ok(Ast::Const(string))
+        Ok(if symbols.is_empty() {
+            Sides::Custom(numbers)
+        } else {
+            Sides::Symbols(symbols)
+        })
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::filtermodifier::FilterModifier;
-    use crate::interpreter::{Ast, Value, DEFAULT_SIDES};
+    /// Parses the bounds of a range die after the opening `[`.
+    pub fn parse_range(&mut self, options: &Options) -> Result<Sides<Box<Ast>>, Options> {
+        let lo = self.parse_bound(options)?;
+        self.accept_string("..", options)
+            .map_err(|e| e.message("expected `..` between the bounds of a range"))?;
+        let hi = self.parse_bound(options)?;
+        self.accept(']', options)
+            .map_err(|e| e.message("missing closing bracket"))?;
 
-    #[test]
-    pub fn add() {
-        let mut p = Parser::new("3 + 5");
-        assert_eq!(
-            p.parse().unwrap().interp(&mut Vec::new()).unwrap(),
-            Value::Int(8)
-        );
+        Ok(Sides::Range(Box::new(lo), Box::new(hi)))
     }
 
-    #[test]
-    pub fn sub() {
-        let mut p = Parser::new("3 - 5");
-        assert_eq!(
-            p.parse().unwrap().interp(&mut Vec::new()).unwrap(),
-            Value::Int(-2)
-        );
+    /// Parses a bound of a range die: a number, or any expression in advanced mode.
+    pub fn parse_bound(&mut self, options: &Options) -> Result<Ast, Options> {
+        let start = self.start();
+        if self.advanced {
+            self.parse_numeric(options)
+        } else if self.accept('-', options).is_ok() {
+            let value = self.parse_number(options)?;
+            Ok(Ast::Minus(Box::new(value), self.span(start)))
+        } else {
+            self.parse_number(&options.clone().add('-'))
+        }
     }
 
-    #[test]
-    pub fn mul() {
-        let mut p = Parser::new("3 * 5");
-        assert_eq!(
-            p.parse().unwrap().interp(&mut Vec::new()).unwrap(),
-            Value::Int(15)
-        );
-    }
+    /// Parses a word made of letters, digits and underscores, starting with a letter.
+    pub fn parse_symbol(&mut self, options: &Options) -> Result<String, Options> {
+        self.skip_whitespace();
 
-    #[test]
-    pub fn div() {
-        let mut p = Parser::new("15/3");
-        let ast = p.parse().unwrap();
-        assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Float(5.0));
-    }
+        let mut symbol = String::new();
+        while let Some(&c) = self.expr.peek() {
+            if c.is_alphabetic() || (!symbol.is_empty() && (c.is_alphanumeric() || c == '_')) {
+                symbol.push(c);
+                self.advance();
+                self.last = self.offset;
+            } else {
+                break;
+            }
+        }
 
-    #[test]
-    pub fn pemdas() {
-        let mut p = Parser::new("3 * 5 + -5");
-        let ast = p.parse().unwrap();
-        assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Int(10));
+        if symbol.is_empty() {
+            Err(options.clone().add_str("a-z").pos(self.pos))
+        } else {
+            Ok(symbol)
+        }
     }
 
-    #[test]
-    pub fn parens() {
-        let mut p = Parser::new("3 * (5 + -5)");
-        let ast = p.parse().unwrap();
-        assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Int(0))
+    pub fn parse_number_or_percent(&mut self, options: Options) -> Result<Ast, Options> {
+        let start = self.start();
+        if self.accept('%', &options).is_ok() {
+            Ok(Ast::Const(Value::Int(100), self.span(start)))
+        } else {
+            self.parse_number(&options.add('%'))
+        }
+    }
+
+    /// Parses a number like `12`, `1_000`, `2.5` or `1.5e3`. Digits can be separated by
+    /// underscores, and a number with a decimal point or an exponent is a float.
+    pub fn parse_number(&mut self, options: &Options) -> Result<Ast, Options> {
+        let start = self.start();
+        let pos = self.pos;
+
+        // The digits before or after the decimal point can be left out, like in `.5` and `2.`
+        let point_first = self.expr.peek() == Some(&'.')
+            && self.expr.clone().nth(1).is_some_and(|c| c.is_ascii_digit());
+        if !point_first && !self.peek_digit() {
+            return Err(options
+                .clone()
+                .add_str("0-9")
+                .add('(')
+                .message("tried to parse a number"));
+        }
+
+        let mut number = String::new();
+        if point_first {
+            number.push('0');
+        } else {
+            self.parse_digits(&mut number, "expected a digit")?;
+        }
+
+        let mut float = false;
+        // Two dots are the separator of a range like `d[1..6]`, not part of a number
+        if self.expr.peek() == Some(&'.') && self.expr.clone().nth(1) != Some('.') {
+            self.advance();
+            number.push('.');
+            float = true;
+            if self.expr.peek().is_some_and(char::is_ascii_digit) {
+                self.parse_digits(&mut number, "expected a digit")?;
+            } else {
+                number.push('0');
+                self.last = self.offset;
+            }
+        }
+
+        if let Some(e @ ('e' | 'E')) = self.expr.peek().copied() {
+            self.advance();
+            number.push(e);
+            if let Some(sign @ ('+' | '-')) = self.expr.peek().copied() {
+                self.advance();
+                number.push(sign);
+            }
+            float = true;
+            self.parse_digits(&mut number, "expected a digit in the exponent")?;
+        }
+
+        if self.expr.peek() == Some(&'.') && self.expr.clone().nth(1) != Some('.') {
+            return Err(self.malformed(if number.contains('.') {
+                "a number can only have one decimal point"
+            } else {
+                "the exponent of a number has to be a whole number"
+            }));
+        }
+
+        let value = if float {
+            // Anything made of digits with one decimal point and an exponent is a valid float
+            Value::Float(number.parse().unwrap())
+        } else {
+            Value::parse_int(&number).ok_or_else(|| {
+                Options::new(self.source)
+                    .pos(pos)
+                    .message("the number is too large")
+            })?
+        };
+
+        Ok(Ast::Const(value, self.span(start)))
+    }
+
+    /// Adds one or more digits to `number`, which can be separated by single underscores.
+    fn parse_digits(&mut self, number: &mut String, mut message: &str) -> Result<(), Options> {
+        loop {
+            match self.expr.peek() {
+                Some(&c) if c.is_ascii_digit() => number.push(c),
+                _ => return Err(self.malformed(message).add_str("0-9")),
+            }
+            self.advance();
+
+            match self.expr.peek() {
+                Some(c) if c.is_ascii_digit() => {}
+                Some('_') => {
+                    self.advance();
+                    message = "expected a digit after `_`";
+                }
+                _ => break,
+            }
+        }
+
+        self.last = self.offset;
+        Ok(())
+    }
+
+    /// The error for a number that's malformed at the current position.
+    fn malformed(&self, message: &str) -> Options {
+        Options::new(self.source).pos(self.pos).message(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filtermodifier::{
+        Comparison, ExplodeModifier, FilterModifier, RerollModifier, SuccessModifier, Target,
+    };
+    use crate::interpreter::{Ast, Value, DEFAULT_SIDES};
+    use crate::sides::Sides;
+
+    /// A number written at `start` in the source.
+    fn num(value: &str, start: usize) -> Box<Ast> {
+        let v = if value.contains('.') {
+            Value::Float(value.parse().unwrap())
+        } else {
+            Value::Int(value.parse().unwrap())
+        };
+        Box::new(Ast::Const(v, Span::new(start, start + value.len())))
+    }
+
+    #[test]
+    pub fn add() {
+        let mut p = Parser::new("3 + 5");
+        assert_eq!(
+            p.parse().unwrap().interp(&mut Vec::new()).unwrap(),
+            Value::Int(8)
+        );
+    }
+
+    #[test]
+    pub fn sub() {
+        let mut p = Parser::new("3 - 5");
+        assert_eq!(
+            p.parse().unwrap().interp(&mut Vec::new()).unwrap(),
+            Value::Int(-2)
+        );
+    }
+
+    #[test]
+    pub fn mul() {
+        let mut p = Parser::new("3 * 5");
+        assert_eq!(
+            p.parse().unwrap().interp(&mut Vec::new()).unwrap(),
+            Value::Int(15)
+        );
+    }
+
+    #[test]
+    pub fn div() {
+        let mut p = Parser::new("15/3");
+        let ast = p.parse().unwrap();
+        assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Float(5.0));
+    }
+
+    #[test]
+    pub fn pemdas() {
+        let mut p = Parser::new("3 * 5 + -5");
+        let ast = p.parse().unwrap();
+        assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Int(10));
+    }
+
+    #[test]
+    pub fn parens() {
+        let mut p = Parser::new("3 * (5 + -5)");
+        let ast = p.parse().unwrap();
+        assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Int(0))
     }
 
     #[test]
@@ -414,7 +1079,18 @@ mod tests {
     pub fn dice_none() {
         let mut p = Parser::new("d");
         let ast = p.parse().unwrap();
-        assert_eq!(ast, Ast::Dice(None, None, FilterModifier::None, 0));
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                None,
+                None,
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 1)
+            )
+        );
 
         let mut rolls = Vec::new();
         let res = ast.interp(&mut rolls).unwrap();
@@ -422,7 +1098,7 @@ mod tests {
         assert_eq!(rolls.len(), 1);
 
         let roll = &rolls[0].1;
-        assert_eq!(DEFAULT_SIDES, roll.sides.to_string());
+        assert_eq!(DEFAULT_SIDES.to_string(), roll.sides.to_string());
 
         assert_eq!(res, Value::Int(roll.total));
     }
@@ -435,9 +1111,12 @@ mod tests {
             ast,
             Ast::Dice(
                 None,
-                Some(Box::new(Ast::Const("6".to_string()))),
+                Some(Sides::Number(num("6", 1))),
                 FilterModifier::None,
-                0
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 2)
             )
         );
 
@@ -448,7 +1127,7 @@ mod tests {
 
         let roll = &rolls[0].1;
 
-        assert_eq!(res, Value::Int(roll.total as i64));
+        assert_eq!(res, Value::Int(roll.total));
     }
 
     #[test]
@@ -458,10 +1137,13 @@ mod tests {
         assert_eq!(
             ast,
             Ast::Dice(
-                Some(Box::new(Ast::Const("3".to_string()))),
-                Some(Box::new(Ast::Const("6".to_string()))),
+                Some(num("3", 0)),
+                Some(Sides::Number(num("6", 2))),
                 FilterModifier::None,
-                1
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 3)
             )
         );
 
@@ -474,7 +1156,7 @@ mod tests {
         let roll1 = &rolls[0].1.vals[1];
         let roll2 = &rolls[0].1.vals[2];
 
-        assert_eq!(res, Value::Int((roll0 + roll1 + roll2) as i64));
+        assert_eq!(res, Value::Int(roll0 + roll1 + roll2));
     }
 
     #[test]
@@ -484,10 +1166,13 @@ mod tests {
         assert_eq!(
             ast,
             Ast::Dice(
-                Some(Box::new(Ast::Const("0".to_string()))),
-                Some(Box::new(Ast::Const("6".to_string()))),
+                Some(num("0", 0)),
+                Some(Sides::Number(num("6", 2))),
                 FilterModifier::None,
-                1
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 3)
             )
         );
 
@@ -506,10 +1191,13 @@ mod tests {
         assert_eq!(
             ast,
             Ast::Dice(
-                Some(Box::new(Ast::Const("3.5".to_string()))),
-                Some(Box::new(Ast::Const("6".to_string()))),
+                Some(num("3.5", 0)),
+                Some(Sides::Number(num("6", 4))),
                 FilterModifier::None,
-                3
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 5)
             )
         );
 
@@ -523,10 +1211,13 @@ mod tests {
         assert_eq!(
             ast,
             Ast::Dice(
-                Some(Box::new(Ast::Const("3".to_string()))),
-                Some(Box::new(Ast::Const("3.5".to_string()))),
+                Some(num("3", 0)),
+                Some(Sides::Number(num("3.5", 2))),
                 FilterModifier::None,
-                1
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 5)
             )
         );
 
@@ -540,10 +1231,13 @@ mod tests {
         assert_eq!(
             ast,
             Ast::Dice(
-                Some(Box::new(Ast::Const("3".to_string()))),
-                Some(Box::new(Ast::Const("0".to_string()))),
+                Some(num("3", 0)),
+                Some(Sides::Number(num("0", 2))),
                 FilterModifier::None,
-                1
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 3)
             )
         );
 
@@ -558,9 +1252,15 @@ mod tests {
             ast,
             Ast::Dice(
                 None,
-                Some(Box::new(Ast::Const("100".to_string()))),
+                Some(Sides::Number(Box::new(Ast::Const(
+                    Value::Int(100),
+                    Span::new(1, 2)
+                )))),
                 FilterModifier::None,
-                0
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 2)
             )
         );
 
@@ -571,7 +1271,7 @@ mod tests {
 
         let roll = &rolls[0].1;
 
-        assert_eq!(roll.sides.get(), 100);
+        assert_eq!(roll.sides, Sides::Number(100));
         assert_eq!(res, Value::Int(roll.total));
     }
 
@@ -591,6 +1291,417 @@ mod tests {
         assert_eq!(res, Value::Int(roll.total));
     }
 
+    #[test]
+    pub fn dice_kh_expression() {
+        assert!(Parser::new("4d6kh(1+1)").parse().is_err());
+
+        let mut rolls = Vec::new();
+        Parser::new("4d6kh(1+1)")
+            .advanced()
+            .parse()
+            .unwrap()
+            .interp(&mut rolls)
+            .unwrap();
+        assert_eq!(rolls[0].1.vals.len(), 2);
+    }
+
+    #[test]
+    pub fn dice_explode() {
+        let mut p = Parser::new("4d6!");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                Some(num("4", 0)),
+                Some(Sides::Number(num("6", 2))),
+                FilterModifier::None,
+                ExplodeModifier::Explode(None),
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 4)
+            )
+        );
+
+        let mut rolls = Vec::new();
+        let res = ast.interp(&mut rolls).unwrap();
+
+        let roll = &rolls[0].1;
+        assert!(roll.vals.len() >= 4);
+        assert_eq!(res, Value::Int(roll.total));
+    }
+
+    #[test]
+    pub fn dice_explode_target() {
+        let mut p = Parser::new("d10!>8kh1");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                None,
+                Some(Sides::Number(num("10", 1))),
+                FilterModifier::KeepHighest(num("1", 8)),
+                ExplodeModifier::Explode(Some(Target {
+                    cmp: Comparison::Greater,
+                    value: num("8", 5),
+                })),
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 9)
+            )
+        );
+        assert_eq!(ast.to_string(), "d10!>8kh1");
+    }
+
+    #[test]
+    pub fn dice_explode_then_pool() {
+        let mut p = Parser::new("d6!>4f1");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                None,
+                Some(Sides::Number(num("6", 1))),
+                FilterModifier::None,
+                ExplodeModifier::Explode(None),
+                RerollModifier::None,
+                SuccessModifier::Count(
+                    Target {
+                        cmp: Comparison::Greater,
+                        value: num("4", 4),
+                    },
+                    Some(Target {
+                        cmp: Comparison::Equal,
+                        value: num("1", 6),
+                    })
+                ),
+                Span::new(0, 7)
+            )
+        );
+        assert_eq!(ast.to_string(), "d6!>4f1");
+
+        // With both an explode target and a success count
+        let ast = Parser::new("d6!6>=5f1").parse().unwrap();
+        assert_eq!(ast.to_string(), "d6!6>=5f1");
+        assert!(Parser::new("d!=3f0").parse().is_ok());
+    }
+
+    #[test]
+    pub fn dice_compound() {
+        let mut p = Parser::new("d6!!");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                None,
+                Some(Sides::Number(num("6", 1))),
+                FilterModifier::None,
+                ExplodeModifier::Compound(None),
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 4)
+            )
+        );
+        assert_eq!(ast.to_string(), "d6!!");
+
+        let mut rolls = Vec::new();
+        ast.interp(&mut rolls).unwrap();
+        assert_eq!(rolls[0].1.vals.len(), 1);
+    }
+
+    #[test]
+    pub fn dice_penetrate() {
+        let mut p = Parser::new("3d6!p<=2");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                Some(num("3", 0)),
+                Some(Sides::Number(num("6", 2))),
+                FilterModifier::None,
+                ExplodeModifier::Penetrate(Some(Target {
+                    cmp: Comparison::LessEqual,
+                    value: num("2", 7),
+                })),
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 8)
+            )
+        );
+        assert_eq!(ast.to_string(), "3d6!p<=2");
+    }
+
+    #[test]
+    pub fn dice_reroll() {
+        let mut p = Parser::new("2d20r1");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                Some(num("2", 0)),
+                Some(Sides::Number(num("20", 2))),
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::Reroll(Target {
+                    cmp: Comparison::Equal,
+                    value: num("1", 5),
+                }),
+                SuccessModifier::None,
+                Span::new(0, 6)
+            )
+        );
+
+        let mut rolls = Vec::new();
+        let res = ast.interp(&mut rolls).unwrap();
+
+        let roll = &rolls[0].1;
+        assert_eq!(roll.vals.len(), 2);
+        assert!(!roll.vals.contains(&1));
+        assert_eq!(res, Value::Int(roll.total));
+    }
+
+    #[test]
+    pub fn dice_reroll_display() {
+        for s in ["4d6ro<3", "2d6ra1kh1", "d6r1!"] {
+            assert_eq!(Parser::new(s).parse().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    pub fn dice_reroll_missing_target() {
+        let mut p = Parser::new("2d20r");
+        p.parse().expect_err("parse was okay");
+    }
+
+    #[test]
+    pub fn dice_pool() {
+        let mut p = Parser::new("10d10>=7f1");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                Some(num("10", 0)),
+                Some(Sides::Number(num("10", 3))),
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::Count(
+                    Target {
+                        cmp: Comparison::GreaterEqual,
+                        value: num("7", 7),
+                    },
+                    Some(Target {
+                        cmp: Comparison::Equal,
+                        value: num("1", 9),
+                    })
+                ),
+                Span::new(0, 10)
+            )
+        );
+        assert_eq!(ast.to_string(), "10d10>=7f1");
+
+        let mut rolls = Vec::new();
+        let res = ast.interp(&mut rolls).unwrap();
+
+        let roll = &rolls[0].1;
+        assert_eq!(roll.outcomes.len(), 10);
+        assert!(roll.total <= 10 && roll.total >= -10);
+        assert_eq!(res, Value::Int(roll.total));
+    }
+
+    #[test]
+    pub fn dice_pool_display() {
+        for s in ["5d6=6", "4d6kh3>4", "6d10ro1>8f<3", "3d6<2"] {
+            assert_eq!(Parser::new(s).parse().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    pub fn dice_pool_missing_target() {
+        Parser::new("10d10>=").parse().expect_err("parse was okay");
+        Parser::new("10d10>=7f")
+            .parse()
+            .expect_err("parse was okay");
+    }
+
+    #[test]
+    pub fn dice_explode_missing_target() {
+        let mut p = Parser::new("d10!>");
+        p.parse().expect_err("parse was okay");
+    }
+
+    #[test]
+    pub fn dice_fudge() {
+        let mut p = Parser::new("4dF");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                Some(num("4", 0)),
+                Some(Sides::Fudge),
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 3)
+            )
+        );
+        assert_eq!(ast.to_string(), "4dF");
+
+        let mut rolls = Vec::new();
+        let res = ast.interp(&mut rolls).unwrap();
+
+        let roll = &rolls[0].1;
+        assert_eq!(roll.vals.len(), 4);
+        assert!(roll.vals.iter().all(|v| (-1..=1).contains(v)));
+        assert_eq!(res, Value::Int(roll.total));
+    }
+
+    #[test]
+    pub fn dice_fudge1() {
+        let mut p = Parser::new("dF.1 + 2");
+        let ast = p.parse().unwrap();
+        assert_eq!(ast.to_string(), "dF.1 + 2");
+
+        let mut rolls = Vec::new();
+        ast.interp(&mut rolls).unwrap();
+        assert_eq!(rolls[0].1.sides, Sides::Fudge1);
+    }
+
+    #[test]
+    pub fn dice_custom() {
+        let mut p = Parser::new("3d{-1, 0, 0, 1}");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                Some(num("3", 0)),
+                Some(Sides::Custom(vec![
+                    Box::new(Ast::Minus(num("1", 4), Span::new(3, 5))),
+                    num("0", 7),
+                    num("0", 10),
+                    num("1", 13),
+                ])),
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 15)
+            )
+        );
+        assert_eq!(ast.to_string(), "3d{-1,0,0,1}");
+
+        let mut rolls = Vec::new();
+        let res = ast.interp(&mut rolls).unwrap();
+
+        let roll = &rolls[0].1;
+        assert!(roll.vals.iter().all(|v| (-1..=1).contains(v)));
+        assert_eq!(res, Value::Int(roll.total));
+    }
+
+    #[test]
+    pub fn dice_symbols() {
+        let mut p = Parser::new("2d{hit,hit,miss,crit} + 1");
+        let ast = p.parse().unwrap();
+        assert_eq!(ast.to_string(), "2d{hit,hit,miss,crit} + 1");
+
+        let mut rolls = Vec::new();
+        let res = ast.interp(&mut rolls).unwrap();
+
+        let roll = &rolls[0].1;
+        assert_eq!(roll.total, 0);
+        assert_eq!(res, Value::Int(1));
+        for i in 0..2 {
+            assert!(["hit", "miss", "crit"].contains(&roll.display_val(i).as_str()));
+        }
+    }
+
+    #[test]
+    pub fn dice_symbols_explode() {
+        let error = |s: &str| match Parser::new(s).parse() {
+            Err(RollError::Parse { pos, message, .. }) => (pos, message),
+            other => panic!("expected {} to be an error, got {:?}", s, other),
+        };
+        let message = "dice with symbols can only explode with `!`".to_string();
+
+        assert_eq!(error("3d{a,b}!!"), (7, message.clone()));
+        assert_eq!(error("4d{a,b}!p"), (7, message.clone()));
+        assert_eq!(error("d{hit,miss}r1!p>1"), (13, message));
+
+        // Plain explosions only add dice with faces that exist
+        let mut rolls = Vec::new();
+        Parser::new("4d{a,b}!")
+            .parse()
+            .unwrap()
+            .interp(&mut rolls)
+            .unwrap();
+        let roll = &rolls[0].1;
+        assert!(roll.vals.iter().all(|v| (1..=2).contains(v)));
+        let counted: usize = roll.symbol_counts().iter().map(|(_, n)| n).sum();
+        assert_eq!(counted, roll.vals.len());
+    }
+
+    #[test]
+    pub fn dice_faces_invalid() {
+        for s in ["d{}", "d{1,hit}", "d{1,2", "d{1,,2}"] {
+            Parser::new(s).parse().expect_err(s);
+        }
+    }
+
+    #[test]
+    pub fn dice_range() {
+        let mut p = Parser::new("2d[-3..8]");
+        let ast = p.parse().unwrap();
+        assert_eq!(
+            ast,
+            Ast::Dice(
+                Some(num("2", 0)),
+                Some(Sides::Range(
+                    Box::new(Ast::Minus(num("3", 4), Span::new(3, 5))),
+                    num("8", 7),
+                )),
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                SuccessModifier::None,
+                Span::new(0, 9)
+            )
+        );
+        assert_eq!(ast.to_string(), "2d[-3..8]");
+
+        let mut rolls = Vec::new();
+        let res = ast.interp(&mut rolls).unwrap();
+
+        let roll = &rolls[0].1;
+        assert!(roll.vals.iter().all(|v| (-3..=8).contains(v)));
+        assert_eq!(res, Value::Int(roll.total));
+    }
+
+    #[test]
+    pub fn dice_range_zero() {
+        let mut p = Parser::new("d[0..0]");
+        let ast = p.parse().unwrap();
+        assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Int(0));
+    }
+
+    #[test]
+    pub fn dice_range_reversed() {
+        let mut p = Parser::new("d[9..0]");
+        let ast = p.parse().unwrap();
+        ast.interp(&mut Vec::new()).expect_err("result was okay");
+    }
+
+    #[test]
+    pub fn dice_range_advanced() {
+        let mut p = Parser::new("d[d4..(2d6 + 4)]").advanced();
+        let ast = p.parse().unwrap();
+
+        let mut rolls = Vec::new();
+        ast.interp(&mut rolls).unwrap();
+        assert_eq!(rolls.len(), 3);
+
+        Parser::new("d[d4..8]").parse().expect_err("parse was okay");
+    }
+
     #[test]
     pub fn pow() {
         let mut p = Parser::new("5 ** 2");
@@ -598,6 +1709,257 @@ mod tests {
         assert_eq!(ast.interp(&mut Vec::new()).unwrap(), Value::Int(25));
     }
 
+    #[test]
+    pub fn numbers() {
+        let value = |s: &str| match Parser::new(s).parse().unwrap() {
+            Ast::Const(v, span) => {
+                assert_eq!(span, Span::new(0, s.len()));
+                v
+            }
+            ast => panic!("expected a number, got {:?}", ast),
+        };
+
+        assert_eq!(value("1_000_000"), Value::Int(1_000_000));
+        assert_eq!(value("007"), Value::Int(7));
+        assert_eq!(value("2.5"), Value::Float(2.5));
+        assert_eq!(value(".5"), Value::Float(0.5));
+        assert_eq!(value("1."), Value::Float(1.0));
+        assert_eq!(value("3.e2"), Value::Float(300.0));
+        assert_eq!(
+            Parser::new("2. + .5")
+                .parse()
+                .unwrap()
+                .interp(&mut Vec::new()),
+            Ok(Value::Float(2.5))
+        );
+        assert_eq!(value("1_0.2_5"), Value::Float(10.25));
+        assert_eq!(value("1e3"), Value::Float(1000.0));
+        assert_eq!(value("2.5E-2"), Value::Float(0.025));
+        assert_eq!(value("1e+2"), Value::Float(100.0));
+
+        assert_eq!(
+            Parser::new("1_000d1_0").parse().unwrap().to_string(),
+            "1000d10"
+        );
+    }
+
+    #[test]
+    pub fn malformed_numbers() {
+        let error = |s: &str| match Parser::new(s).parse() {
+            // Later lines say what the parser was trying to do
+            Err(RollError::Parse { pos, message, .. }) => {
+                (pos, message.lines().next().unwrap().to_string())
+            }
+            other => panic!("expected {} to be malformed, got {:?}", s, other),
+        };
+
+        assert_eq!(
+            error("1.2.3"),
+            (3, "a number can only have one decimal point".to_string())
+        );
+        assert_eq!(
+            error("1e5.5"),
+            (
+                3,
+                "the exponent of a number has to be a whole number".to_string()
+            )
+        );
+        assert_eq!(error("1__0"), (2, "expected a digit after `_`".to_string()));
+        assert_eq!(error("10_"), (3, "expected a digit after `_`".to_string()));
+        assert_eq!(
+            error("1e"),
+            (2, "expected a digit in the exponent".to_string())
+        );
+        assert_eq!(
+            error("4d6 + 3e-"),
+            (9, "expected a digit in the exponent".to_string())
+        );
+        assert_eq!(
+            error("d6kh2.5.1"),
+            (7, "a number can only have one decimal point".to_string())
+        );
+        assert!(Parser::new("...").parse().is_err());
+        assert!(Parser::new(". 5").parse().is_err());
+        assert!(Parser::new("1 2").parse().is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    pub fn number_too_large() {
+        // Also in the modifiers of a roll, which can't be parsed as a number instead
+        for (s, expected) in [
+            ("1 + 99999999999999999999", 4),
+            ("d6!99999999999999999999", 3),
+            ("9dro99999999999999999999", 4),
+        ] {
+            match Parser::new(s).parse() {
+                Err(RollError::Parse { pos, message, .. }) => {
+                    assert_eq!(pos, expected);
+                    assert!(message.ends_with("the number is too large"));
+                }
+                other => panic!("expected a parse error, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "bigint"))]
+    pub fn overflow() {
+        let interp = |s: &str| Parser::new(s).parse().unwrap().interp(&mut Vec::new());
+
+        for s in [
+            "10 ** 100",
+            "2 ** 4294967296",
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "(-9223372036854775807 - 1) * -1",
+            "-(-9223372036854775807 - 1)",
+            "2d[9223372036854775807..9223372036854775807]",
+            "d[9223372036854775807..9223372036854775807]!!",
+        ] {
+            assert_eq!(interp(s), Err(RollError::Overflow), "{}", s);
+        }
+
+        assert_eq!(interp("1 ** 4294967297"), Ok(Value::Int(1)));
+        assert_eq!(interp("(0 - 1) ** 4294967297"), Ok(Value::Int(-1)));
+        assert_eq!(
+            interp("(-9223372036854775807 - 1) mod -1"),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(interp("1 // 0"), Ok(Value::Float(f64::INFINITY)));
+        assert!(matches!(interp("5 mod 0"), Ok(Value::Float(v)) if v.is_nan()));
+    }
+
+    #[test]
+    pub fn functions() {
+        let ast = Parser::new("1 + max(1, d4 - 2)").parse().unwrap();
+        assert_eq!(ast.to_string(), "1 + max(1, d4 - 2)");
+        match ast {
+            Ast::Add(_, r, _) => {
+                assert!(matches!(*r, Ast::Call(Function::Max, ref args, _) if args.len() == 2));
+                assert_eq!(r.span(), Span::new(4, 18));
+            }
+            ast => panic!("expected an addition, got {:?}", ast),
+        }
+
+        match Parser::new("floor(1, 2)").parse() {
+            Err(RollError::Parse { message, .. }) => {
+                assert!(
+                    message.starts_with("`floor` takes 1 argument"),
+                    "{}",
+                    message
+                )
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+        assert!(Parser::new("max(1, 2").parse().is_err());
+        assert!(Parser::new("sqrt(4)").parse().is_err());
+
+        // `d(6)` is still a die
+        let mut p = Parser::new("d(6)");
+        p.advanced = true;
+        assert!(matches!(p.parse().unwrap(), Ast::Dice(..)));
+    }
+
+    #[test]
+    pub fn conditions() {
+        let interp = |s: &str| Parser::new(s).parse().unwrap().interp(&mut Vec::new());
+
+        assert_eq!(interp("2 + 3 >= 5"), Ok(Value::Bool(true)));
+        assert_eq!(interp("1.5 < 1"), Ok(Value::Bool(false)));
+        assert_eq!(interp("3 == 3.0"), Ok(Value::Bool(true)));
+        assert_eq!(interp("(0 mod 0) != (0 mod 0)"), Ok(Value::Bool(true)));
+        assert_eq!(interp("(0 mod 0) <= 1"), Ok(Value::Bool(false)));
+        assert_eq!(
+            interp("1 > 2 or 2 > 1 and not 1 > 2"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(interp("not not 1 > 2"), Ok(Value::Bool(false)));
+        assert_eq!(interp("1 > 2 ? 10 : 20"), Ok(Value::Int(20)));
+        assert_eq!(interp("1 < 2 ? 2 < 1 : 1 < 2"), Ok(Value::Bool(false)));
+        assert_eq!(interp("1 > 2 ? 1 : 2 > 3 ? 2 : 3"), Ok(Value::Int(3)));
+        assert_eq!(interp("1 + (1 < 2 ? 5 : 6) * 2"), Ok(Value::Int(11)));
+        assert_eq!(interp("max(0, 1 < 2 ? 5 : 6)"), Ok(Value::Int(5)));
+
+        let ast = Parser::new("d20 + 5 >= 15 ? 2d6 + 3 : 0").parse().unwrap();
+        assert_eq!(ast.to_string(), "d20 + 5 >= 15 ? 2d6 + 3 : 0");
+        assert!(matches!(ast, Ast::If(ref c, ..) if matches!(**c, Ast::GreaterEqual(..))));
+
+        assert_eq!(
+            Value::Bool(true) + Value::Int(1),
+            Err(RollError::NotANumber("true".to_string()))
+        );
+    }
+
+    #[test]
+    pub fn comparison_after_dice() {
+        let parse = |s: &str| Parser::new(s).parse().unwrap();
+
+        // Without a space the comparison counts successes
+        assert!(matches!(parse("d6>3"), Ast::Dice(..)));
+        assert!(matches!(parse("d6 > 3"), Ast::Greater(..)));
+        assert!(matches!(parse("3d6>4 > 1"), Ast::Greater(..)));
+        assert!(matches!(parse("d6!=3"), Ast::Dice(..)));
+        assert!(matches!(parse("d6 != 3"), Ast::NotEqual(..)));
+        assert!(matches!(parse("d6! >= 3"), Ast::GreaterEqual(..)));
+        assert_eq!(parse("d6! >= 3").to_string(), "d6! >= 3");
+        assert_eq!(parse("d6! != 3").to_string(), "d6! != 3");
+        assert_eq!(parse("not d6! != 3").to_string(), "not d6! != 3");
+
+        // The same roll with and without spaces: a number of successes, or true or false
+        let pool = parse("10d10>=7").interp(&mut Vec::new()).unwrap();
+        assert!(matches!(pool, Value::Int(0..=10)), "{:?}", pool);
+        let condition = parse("10d10 >= 7").interp(&mut Vec::new()).unwrap();
+        assert!(matches!(condition, Value::Bool(_)), "{:?}", condition);
+        let condition = parse("(10d10)>=7").interp(&mut Vec::new()).unwrap();
+        assert!(matches!(condition, Value::Bool(_)), "{:?}", condition);
+        assert!(matches!(parse("10d10 >=7"), Ast::GreaterEqual(..)));
+        assert!(matches!(parse("10d10>= 7"), Ast::Dice(..)));
+    }
+
+    #[test]
+    pub fn condition_kinds() {
+        let error = |s: &str| match Parser::new(s).parse() {
+            Err(RollError::Parse { pos, message, .. }) => (pos, message),
+            other => panic!("expected {} to be an error, got {:?}", s, other),
+        };
+        let number = "this is true or false, but a number is needed here".to_string();
+        let condition = "this is a number, but true or false is needed here".to_string();
+
+        assert_eq!(error("(d6 > 3) + 1"), (1, number.clone()));
+        assert_eq!(error("-(1 < 2)"), (2, number.clone()));
+        assert_eq!(error("max(1, 2 < 3)"), (7, number.clone()));
+        assert_eq!(error("(1 < 2) < 3"), (1, number));
+        assert_eq!(error("not 3"), (4, condition.clone()));
+        assert_eq!(error("1 > 2 and d6"), (10, condition.clone()));
+        assert_eq!(error("d6 ? 1 : 2"), (0, condition));
+        assert_eq!(
+            error("1 > 2 ? 1 : 2 > 1"),
+            (
+                12,
+                "both branches have to be numbers, or both have to be true or false".to_string()
+            )
+        );
+        assert!(error("1 > 2 ? 1").1.contains("expected `:`"));
+        assert!(Parser::new("1 < 2 < 3").parse().is_err());
+    }
+
+    #[test]
+    pub fn advanced_parentheses() {
+        // Every level used to be parsed twice, once as the number of dice of a roll
+        let s = format!("{}1{}", "(".repeat(40), ")".repeat(40));
+        let mut p = Parser::new(&s);
+        p.advanced = true;
+        assert_eq!(
+            p.parse().unwrap(),
+            Ast::Const(Value::Int(1), Span::new(40, 41))
+        );
+
+        let mut p = Parser::new("((2))d6");
+        p.advanced = true;
+        assert_eq!(p.parse().unwrap().to_string(), "2d6");
+    }
+
     #[test]
     pub fn compound() {
         let mut p = Parser::new("(3d5)d(5d3)");
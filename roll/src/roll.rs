@@ -1,24 +1,228 @@
-use crate::filtermodifier::FilterModifier;
+use crate::error::RollError;
+use crate::filtermodifier::{
+    Comparison, ExplodeModifier, FilterModifier, RerollModifier, SuccessModifier, Target,
+};
+use crate::limits::Limits;
+use crate::sides::Sides;
+use core::fmt;
 use rand_core::RngCore;
-use std::num::NonZeroU64;
 
 #[derive(Debug, Clone)]
 pub struct Roll {
-    pub vals: Vec<u64>,
+    pub vals: Vec<i64>,
     pub total: i64,
-    pub sides: NonZeroU64,
+    pub sides: Sides<i64>,
+    /// Every reroll that happened, as the original value and the value it was rerolled into.
+    pub rerolls: Vec<(i64, i64)>,
+    /// For dice pools, whether each value in `vals` counted as a success or failure.
+    /// Empty for normal rolls.
+    pub outcomes: Vec<Outcome>,
+    /// How many dice explosions and `ra` rerolls rolled on top of the ones asked for.
+    pub extra: u64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Outcome {
+    Success,
+    Failure,
+    Neither,
+}
+
+impl Roll {
+    /// Formats a single value, marking successes with `*` and failures with `x`.
+    pub fn display_val(&self, index: usize) -> String {
+        let mark = match self.outcomes.get(index) {
+            Some(Outcome::Success) => "*",
+            Some(Outcome::Failure) => "x",
+            _ => "",
+        };
+
+        format!("{}{}", self.sides.display_face(self.vals[index]), mark)
+    }
+
+    /// For dice with symbols on their faces, how many times each symbol came up, in the order
+    /// the faces are written. Symbols that didn't come up are left out, and for other dice
+    /// this is empty.
+    pub fn symbol_counts(&self) -> Vec<(String, usize)> {
+        let faces = match &self.sides {
+            Sides::Symbols(faces) => faces,
+            _ => return Vec::new(),
+        };
+
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for face in faces {
+            let n = self
+                .vals
+                .iter()
+                .filter(|&&v| self.sides.display_face(v) == *face)
+                .count();
+            if n > 0 && !counts.iter().any(|(f, _)| f == face) {
+                counts.push((face.clone(), n));
+            }
+        }
+        counts
+    }
+
+    /// Replaces the total with the number of successes minus the number of failures.
+    pub fn count_successes(&mut self, sm: SuccessModifier<i64>) {
+        if let SuccessModifier::Count(success, failure) = sm {
+            self.outcomes = self
+                .vals
+                .iter()
+                .map(|&v| {
+                    if success.matches(v) {
+                        Outcome::Success
+                    } else if failure.is_some_and(|f| f.matches(v)) {
+                        Outcome::Failure
+                    } else {
+                        Outcome::Neither
+                    }
+                })
+                .collect();
+
+            self.total = self
+                .outcomes
+                .iter()
+                .map(|o| match o {
+                    Outcome::Success => 1,
+                    Outcome::Failure => -1,
+                    Outcome::Neither => 0,
+                })
+                .sum();
+        }
+    }
+}
+
+impl fmt::Display for Roll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[")?;
+        for index in 0..self.vals.len() {
+            if index != 0 {
+                write!(f, ", ")?;
+            }
+            f.write_str(&self.display_val(index))?;
+        }
+        write!(f, "]")?;
+
+        if !self.rerolls.is_empty() {
+            write!(f, " (rerolled ")?;
+            for (index, (from, to)) in self.rerolls.iter().enumerate() {
+                if index != 0 {
+                    write!(f, ", ")?;
+                }
+                write!(
+                    f,
+                    "{} -> {}",
+                    self.sides.display_face(*from),
+                    self.sides.display_face(*to)
+                )?;
+            }
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Picks a number from 0 up to (but not including) `n`, or any number if `n` is 0.
+///
+/// Taking `next_u64() % n` would make the lower numbers slightly more likely whenever
+/// `n` doesn't divide 2^64, so values from the incomplete block at the top are rejected.
+pub(crate) fn uniform(rng: &mut impl RngCore, n: u64) -> u64 {
+    if n == 0 {
+        return rng.next_u64();
+    }
+
+    // 2^64 mod n, the size of the incomplete block
+    let rejected = (u64::MAX % n + 1) % n;
+    loop {
+        let v = rng.next_u64();
+        if v <= u64::MAX - rejected {
+            return v % n;
+        }
+    }
+}
+
+/// Fisher-Yates shuffle, every order is equally likely.
+pub(crate) fn shuffle<T>(items: &mut [T], rng: &mut impl RngCore) {
+    for i in (1..items.len()).rev() {
+        let j = uniform(rng, i as u64 + 1) as usize;
+        items.swap(i, j);
+    }
 }
 
 pub fn roll_die(
     times: u64,
-    sides: NonZeroU64,
+    sides: Sides<i64>,
     fm: FilterModifier<u64>,
+    em: ExplodeModifier<i64>,
+    rm: RerollModifier<i64>,
+    limits: &Limits,
     mut rng: impl RngCore,
-) -> Roll {
+) -> Result<Roll, RollError> {
+    sides.check()?;
+    limits.check_roll(times, &sides)?;
+
+    let target = match em {
+        ExplodeModifier::Explode(t)
+        | ExplodeModifier::Compound(t)
+        | ExplodeModifier::Penetrate(t) => Some(t.unwrap_or(Target {
+            cmp: Comparison::Equal,
+            value: sides.max(),
+        })),
+        ExplodeModifier::None => None,
+    };
+
     let mut rolls = Vec::new();
-    let range = sides.get();
+    let mut rerolls = Vec::new();
+    let mut extra = 0;
     for _ in 0..times {
-        let roll = (rng.next_u64() % range) + 1;
+        let mut roll = sides.roll(&mut rng);
+
+        match rm {
+            RerollModifier::Reroll(target) => {
+                let mut count = 0;
+                while target.matches(roll) && count < limits.rerolls {
+                    let new = sides.roll(&mut rng);
+                    rerolls.push((roll, new));
+                    roll = new;
+                    count += 1;
+                }
+            }
+            RerollModifier::RerollOnce(target) if target.matches(roll) => {
+                let new = sides.roll(&mut rng);
+                rerolls.push((roll, new));
+                roll = new;
+            }
+            RerollModifier::RerollAdd(target) if target.matches(roll) => {
+                let new = sides.roll(&mut rng);
+                rerolls.push((roll, new));
+                rolls.push(new);
+                extra += 1;
+            }
+            _ => {}
+        }
+
+        if let Some(target) = target {
+            let mut last = roll;
+            let mut explosions = 0;
+            while target.matches(last) && explosions < limits.explosions {
+                last = sides.roll(&mut rng);
+                explosions += 1;
+                extra += 1;
+
+                match em {
+                    ExplodeModifier::Compound(_) => {
+                        roll = roll.checked_add(last).ok_or(RollError::Overflow)?
+                    }
+                    ExplodeModifier::Penetrate(_) => {
+                        rolls.push(last.checked_sub(1).ok_or(RollError::Overflow)?)
+                    }
+                    _ => rolls.push(last),
+                }
+            }
+        }
+
         rolls.push(roll);
     }
 
@@ -43,20 +247,21 @@ pub fn roll_die(
     }
 
     // Shuffle order of results again
-    if !rolls.is_empty() {
-        let range = rolls.len() as u64;
-        for _ in 0..=rolls.len() {
-            let a = rng.next_u64() % range + 1;
-            let b = rng.next_u64() % range + 1;
-            rolls.swap(a as usize - 1, b as usize - 1);
-        }
-    }
+    shuffle(&mut rolls, &mut rng);
+
+    let total = rolls
+        .iter()
+        .try_fold(0i64, |total, v| total.checked_add(sides.value(*v)))
+        .ok_or(RollError::Overflow)?;
 
-    Roll {
-        total: rolls.iter().sum::<u64>() as i64,
+    Ok(Roll {
+        total,
         vals: rolls,
         sides,
-    }
+        rerolls,
+        outcomes: Vec::new(),
+        extra,
+    })
 }
 
 const DIR: &[&str] = &[
@@ -74,24 +279,29 @@ const DIR: &[&str] = &[
 pub fn roll_direction(rng: impl RngCore) -> String {
     let value = roll_die(
         1,
-        NonZeroU64::new(DIR.len() as u64).unwrap(),
+        Sides::Number(DIR.len() as i64),
         FilterModifier::None,
+        ExplodeModifier::None,
+        RerollModifier::None,
+        &Limits::DEFAULT,
         rng,
-    );
+    )
+    .unwrap();
     DIR[value.total as usize - 1].to_string()
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
     use rand_core::{Error, RngCore};
 
-    struct DeterministicRng {
+    /// Returns 0, 1, 2, and so on, so a d6 rolls 1 through 6 in order.
+    pub(crate) struct DeterministicRng {
         value: i64,
     }
 
     impl DeterministicRng {
-        pub fn new() -> Self {
+        pub(crate) fn new() -> Self {
             Self { value: -1 }
         }
     }
@@ -116,14 +326,174 @@ mod tests {
         }
     }
 
+    /// SplitMix64, a small but statistically sound generator for the distribution tests.
+    pub(crate) struct SeededRng {
+        pub(crate) state: u64,
+    }
+
+    impl RngCore for SeededRng {
+        fn next_u32(&mut self) -> u32 {
+            (self.next_u64() >> 32) as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = self.state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            z ^ (z >> 31)
+        }
+
+        fn fill_bytes(&mut self, _: &mut [u8]) {
+            unimplemented!()
+        }
+
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), Error> {
+            unimplemented!()
+        }
+    }
+
+    /// Returns values from a list, to check which values get rejected.
+    struct SequenceRng(Vec<u64>);
+
+    impl RngCore for SequenceRng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0.remove(0)
+        }
+
+        fn fill_bytes(&mut self, _: &mut [u8]) {
+            unimplemented!()
+        }
+
+        fn try_fill_bytes(&mut self, _: &mut [u8]) -> Result<(), Error> {
+            unimplemented!()
+        }
+    }
+
+    /// The chi-square value that a fair die stays below 99.9% of the time,
+    /// using the Wilson-Hilferty approximation.
+    fn chi_square_limit(degrees: f64) -> f64 {
+        const Z: f64 = 3.090;
+        let a = 2.0 / (9.0 * degrees);
+        degrees * (1.0 - a + Z * a.sqrt()).powi(3)
+    }
+
+    /// Rolls a die many times and checks the faces come up as often as expected.
+    fn assert_fair(sides: Sides<i64>, faces: &[(i64, f64)]) {
+        const ROLLS: u64 = 60_000;
+        let roll = roll_die(
+            ROLLS,
+            sides.clone(),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits {
+                dice_per_roll: ROLLS,
+                ..Limits::DEFAULT
+            },
+            SeededRng { state: 1234 },
+        )
+        .unwrap();
+
+        let mut chi_square = 0.0;
+        for (face, chance) in faces {
+            let observed = roll.vals.iter().filter(|v| *v == face).count() as f64;
+            let expected = chance * ROLLS as f64;
+            chi_square += (observed - expected).powi(2) / expected;
+        }
+
+        let limit = chi_square_limit(faces.len() as f64 - 1.0);
+        assert!(
+            chi_square < limit,
+            "d{} isn't fair: chi-square {} >= {}",
+            sides,
+            chi_square,
+            limit
+        );
+    }
+
+    fn evenly(faces: impl Iterator<Item = i64>) -> Vec<(i64, f64)> {
+        let faces: Vec<_> = faces.collect();
+        let chance = 1.0 / faces.len() as f64;
+        faces.into_iter().map(|f| (f, chance)).collect()
+    }
+
+    #[test]
+    fn test_fair_number() {
+        for n in [2, 6, 7, 20, 100] {
+            assert_fair(Sides::Number(n), &evenly(1..=n));
+        }
+    }
+
+    #[test]
+    fn test_fair_fudge() {
+        assert_fair(Sides::Fudge, &evenly(-1..=1));
+        assert_fair(
+            Sides::Fudge1,
+            &[(-1, 1.0 / 6.0), (0, 4.0 / 6.0), (1, 1.0 / 6.0)],
+        );
+    }
+
+    #[test]
+    fn test_fair_range_and_custom() {
+        assert_fair(Sides::Range(0, 9), &evenly(0..=9));
+        assert_fair(Sides::Range(-3, 8), &evenly(-3..=8));
+        assert_fair(
+            Sides::Custom(vec![1, 1, 2, 3]),
+            &[(1, 0.5), (2, 0.25), (3, 0.25)],
+        );
+    }
+
+    #[test]
+    fn test_fair_shuffle() {
+        const SHUFFLES: u64 = 60_000;
+        let mut rng = SeededRng { state: 42 };
+        let mut counts = std::collections::HashMap::new();
+
+        for _ in 0..SHUFFLES {
+            let mut items = [1, 2, 3, 4];
+            shuffle(&mut items, &mut rng);
+            *counts.entry(items).or_insert(0u64) += 1;
+        }
+
+        // All 24 orders should be equally likely
+        assert_eq!(counts.len(), 24);
+        let expected = SHUFFLES as f64 / 24.0;
+        let chi_square: f64 = counts
+            .values()
+            .map(|&c| (c as f64 - expected).powi(2) / expected)
+            .sum();
+        assert!(chi_square < chi_square_limit(23.0));
+    }
+
+    #[test]
+    fn test_uniform_rejects_incomplete_block() {
+        // 2^64 mod 6 is 4, so the top 4 values would make 1 through 4 more likely than 5 and 6
+        let mut rng = SequenceRng(vec![u64::MAX, u64::MAX - 3, u64::MAX - 4]);
+        assert_eq!(uniform(&mut rng, 6), (u64::MAX - 4) % 6);
+        assert!(rng.0.is_empty());
+
+        // Powers of two divide 2^64, so nothing is rejected
+        let mut rng = SequenceRng(vec![u64::MAX]);
+        assert_eq!(uniform(&mut rng, 8), 7);
+    }
+
     #[test]
     fn test_kl() {
         let roll = roll_die(
             6,
-            NonZeroU64::new(6).unwrap(),
+            Sides::Number(6),
             FilterModifier::KeepLowest(3),
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
             DeterministicRng::new(),
-        );
+        )
+        .unwrap();
 
         assert_eq!(roll.vals.len(), 3);
         assert_eq!(roll.total, 6);
@@ -133,11 +503,568 @@ mod tests {
     fn test_dl_overflow() {
         let roll = roll_die(
             100,
-            NonZeroU64::new(6).unwrap(),
+            Sides::Number(6),
             FilterModifier::DropLowest(300),
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
             DeterministicRng::new(),
-        );
+        )
+        .unwrap();
         assert_eq!(roll.vals.len(), 0);
         assert_eq!(roll.total, 0);
     }
+
+    #[test]
+    fn test_filter_edges() {
+        // Rolls 1 through 4, returning how many dice are kept and their total
+        let roll = |times, fm| {
+            let roll = roll_die(
+                times,
+                Sides::Number(6),
+                fm,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                &Limits::DEFAULT,
+                DeterministicRng::new(),
+            )
+            .unwrap();
+            (roll.vals.len(), roll.total)
+        };
+
+        for n in [4, 5, u64::MAX] {
+            assert_eq!(roll(4, FilterModifier::KeepLowest(n)), (4, 10));
+            assert_eq!(roll(4, FilterModifier::KeepHighest(n)), (4, 10));
+            assert_eq!(roll(4, FilterModifier::DropLowest(n)), (0, 0));
+            assert_eq!(roll(4, FilterModifier::DropHighest(n)), (0, 0));
+        }
+
+        assert_eq!(roll(4, FilterModifier::KeepLowest(0)), (0, 0));
+        assert_eq!(roll(4, FilterModifier::KeepHighest(0)), (0, 0));
+        assert_eq!(roll(4, FilterModifier::DropLowest(0)), (4, 10));
+        assert_eq!(roll(4, FilterModifier::DropHighest(0)), (4, 10));
+
+        assert_eq!(roll(4, FilterModifier::KeepLowest(1)), (1, 1));
+        assert_eq!(roll(4, FilterModifier::KeepHighest(1)), (1, 4));
+        assert_eq!(roll(4, FilterModifier::DropLowest(1)), (3, 9));
+        assert_eq!(roll(4, FilterModifier::DropHighest(1)), (3, 6));
+
+        for fm in [
+            FilterModifier::KeepLowest(1),
+            FilterModifier::KeepHighest(1),
+            FilterModifier::DropLowest(1),
+            FilterModifier::DropHighest(1),
+            FilterModifier::None,
+        ] {
+            assert_eq!(roll(0, fm), (0, 0));
+        }
+    }
+
+    #[test]
+    fn test_filter_check() {
+        assert_eq!(
+            FilterModifier::KeepLowest(0).check(),
+            Ok(FilterModifier::KeepLowest(0))
+        );
+        assert_eq!(
+            FilterModifier::DropHighest(i64::MAX).check(),
+            Ok(FilterModifier::DropHighest(i64::MAX as u64))
+        );
+        assert_eq!(FilterModifier::None.check(), Ok(FilterModifier::None));
+
+        for fm in [
+            FilterModifier::KeepLowest(-1),
+            FilterModifier::KeepHighest(-1),
+            FilterModifier::DropLowest(-1),
+            FilterModifier::DropHighest(i64::MIN),
+        ] {
+            assert!(matches!(fm.check(), Err(RollError::InvalidFilterCount(_))));
+        }
+    }
+
+    #[test]
+    fn test_invalid_sides() {
+        let roll = |sides| {
+            roll_die(
+                1,
+                sides,
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                &Limits::DEFAULT,
+                DeterministicRng::new(),
+            )
+            .unwrap_err()
+        };
+
+        assert_eq!(roll(Sides::Number(0)), RollError::ZeroSides(0));
+        assert_eq!(roll(Sides::Number(-6)), RollError::ZeroSides(-6));
+        assert_eq!(roll(Sides::Range(2, 1)), RollError::EmptyRange(2, 1));
+        assert_eq!(roll(Sides::Custom(Vec::new())), RollError::ZeroSides(0));
+        assert_eq!(roll(Sides::Symbols(Vec::new())), RollError::ZeroSides(0));
+    }
+
+    #[test]
+    fn test_explode() {
+        // Rolls 1 through 6, the 6 explodes into a 1
+        let roll = roll_die(
+            6,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::Explode(None),
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 7);
+        assert_eq!(roll.total, 22);
+    }
+
+    #[test]
+    fn test_explode_target() {
+        // Rolls 1, 2, 3 and 2, the 3 explodes into a 4 which explodes again into a 1
+        let roll = roll_die(
+            4,
+            Sides::Number(4),
+            FilterModifier::None,
+            ExplodeModifier::Explode(Some(Target {
+                cmp: Comparison::GreaterEqual,
+                value: 3,
+            })),
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 6);
+        assert_eq!(roll.total, 13);
+    }
+
+    #[test]
+    fn test_compound() {
+        // Rolls 1 through 6, the 6 compounds with a 1 into a 7
+        let roll = roll_die(
+            6,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::Compound(None),
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 6);
+        assert!(roll.vals.contains(&7));
+        assert_eq!(roll.total, 22);
+    }
+
+    #[test]
+    fn test_penetrate() {
+        // Rolls 1 through 6, the 6 penetrates into a 1 which counts as 0
+        let roll = roll_die(
+            6,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::Penetrate(None),
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 7);
+        assert!(roll.vals.contains(&0));
+        assert_eq!(roll.total, 21);
+    }
+
+    #[test]
+    fn test_penetrate_keeps_exploding_on_max() {
+        // Rolls a 3 which penetrates into a 4 (counts as 3) and again into a 1 (counts as 0)
+        let roll = roll_die(
+            1,
+            Sides::Number(4),
+            FilterModifier::None,
+            ExplodeModifier::Penetrate(Some(Target {
+                cmp: Comparison::GreaterEqual,
+                value: 3,
+            })),
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng { value: 1 },
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 3);
+        assert_eq!(roll.total, 6);
+    }
+
+    #[test]
+    fn test_reroll() {
+        // Rolls a 1 which is rerolled into a 2 and then a 3, followed by a 4
+        let roll = roll_die(
+            2,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::Reroll(Target {
+                cmp: Comparison::Less,
+                value: 3,
+            }),
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 2);
+        assert_eq!(roll.total, 7);
+        assert_eq!(roll.rerolls, vec![(1, 2), (2, 3)]);
+    }
+
+    #[test]
+    fn test_reroll_once() {
+        // Rolls a 1 which is rerolled into a 2, which is kept
+        let roll = roll_die(
+            1,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::RerollOnce(Target {
+                cmp: Comparison::Less,
+                value: 3,
+            }),
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals, vec![2]);
+        assert_eq!(roll.rerolls, vec![(1, 2)]);
+    }
+
+    #[test]
+    fn test_reroll_add() {
+        // Rolls a 1 which is rerolled into a 2, both are kept
+        let roll = roll_die(
+            1,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::RerollAdd(Target {
+                cmp: Comparison::Equal,
+                value: 1,
+            }),
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 2);
+        assert_eq!(roll.total, 3);
+        assert_eq!(roll.rerolls, vec![(1, 2)]);
+        assert_eq!(roll.extra, 1);
+    }
+
+    #[test]
+    fn test_reroll_cap() {
+        let roll = roll_die(
+            1,
+            Sides::Number(1),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::Reroll(Target {
+                cmp: Comparison::Equal,
+                value: 1,
+            }),
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals, vec![1]);
+        assert_eq!(roll.rerolls.len() as u64, Limits::DEFAULT.rerolls);
+        // Rerolls that replace a die don't add one
+        assert_eq!(roll.extra, 0);
+    }
+
+    #[test]
+    fn test_count_successes() {
+        let mut roll = roll_die(
+            6,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+        roll.count_successes(SuccessModifier::Count(
+            Target {
+                cmp: Comparison::GreaterEqual,
+                value: 5,
+            },
+            None,
+        ));
+
+        assert_eq!(roll.total, 2);
+        assert_eq!(
+            roll.outcomes
+                .iter()
+                .filter(|o| **o == Outcome::Neither)
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn test_count_failures() {
+        let mut roll = roll_die(
+            6,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+        roll.count_successes(SuccessModifier::Count(
+            Target {
+                cmp: Comparison::Equal,
+                value: 6,
+            },
+            Some(Target {
+                cmp: Comparison::Less,
+                value: 3,
+            }),
+        ));
+
+        assert_eq!(roll.total, -1);
+        for (val, outcome) in roll.vals.iter().zip(&roll.outcomes) {
+            match val {
+                6 => assert_eq!(*outcome, Outcome::Success),
+                1 | 2 => assert_eq!(*outcome, Outcome::Failure),
+                _ => assert_eq!(*outcome, Outcome::Neither),
+            }
+        }
+    }
+
+    #[test]
+    fn test_fudge() {
+        let roll = roll_die(
+            6,
+            Sides::Fudge,
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.iter().filter(|v| **v == -1).count(), 2);
+        assert_eq!(roll.vals.iter().filter(|v| **v == 0).count(), 2);
+        assert_eq!(roll.vals.iter().filter(|v| **v == 1).count(), 2);
+        assert_eq!(roll.total, 0);
+    }
+
+    #[test]
+    fn test_fudge1() {
+        let roll = roll_die(
+            6,
+            Sides::Fudge1,
+            FilterModifier::KeepHighest(1),
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals, vec![1]);
+        assert_eq!(roll.to_string(), "[+]");
+    }
+
+    #[test]
+    fn test_custom() {
+        let roll = roll_die(
+            6,
+            Sides::Custom(vec![2, 4, 6]),
+            FilterModifier::None,
+            ExplodeModifier::Explode(None),
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert!(roll.vals.iter().all(|v| [2, 4, 6].contains(v)));
+        assert_eq!(roll.total, 30);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let sides = Sides::Symbols(vec!["hit".to_string(), "miss".to_string()]);
+        let roll = roll_die(
+            2,
+            sides,
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.total, 0);
+        let mut faces: Vec<_> = (0..2).map(|i| roll.display_val(i)).collect();
+        faces.sort();
+        assert_eq!(faces, vec!["hit", "miss"]);
+        assert_eq!(
+            roll.symbol_counts(),
+            vec![("hit".to_string(), 1), ("miss".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_symbol_counts() {
+        // Faces that are written more than once are counted together
+        let sides = Sides::Symbols(vec!["hit".into(), "miss".into(), "hit".into()]);
+        let roll = roll_die(
+            3,
+            sides,
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            roll.symbol_counts(),
+            vec![("hit".to_string(), 2), ("miss".to_string(), 1)]
+        );
+
+        let roll = roll_die(
+            3,
+            Sides::Number(6),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+        assert!(roll.symbol_counts().is_empty());
+    }
+
+    #[test]
+    fn test_range() {
+        let roll = roll_die(
+            10,
+            Sides::Range(0, 9),
+            FilterModifier::None,
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        let mut vals = roll.vals.clone();
+        vals.sort_unstable();
+        assert_eq!(vals, (0..=9).collect::<Vec<_>>());
+        assert_eq!(roll.total, 45);
+    }
+
+    #[test]
+    fn test_range_negative() {
+        let roll = roll_die(
+            3,
+            Sides::Range(-5, -3),
+            FilterModifier::KeepHighest(1),
+            ExplodeModifier::None,
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals, vec![-3]);
+    }
+
+    #[test]
+    fn test_explode_cap() {
+        let roll = roll_die(
+            1,
+            Sides::Number(1),
+            FilterModifier::None,
+            ExplodeModifier::Explode(None),
+            RerollModifier::None,
+            &Limits::DEFAULT,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len() as u64, Limits::DEFAULT.explosions + 1);
+        assert_eq!(roll.extra, Limits::DEFAULT.explosions);
+    }
+
+    #[test]
+    fn test_custom_caps() {
+        let limits = Limits {
+            explosions: 3,
+            rerolls: 2,
+            ..Limits::DEFAULT
+        };
+        let roll = roll_die(
+            1,
+            Sides::Number(1),
+            FilterModifier::None,
+            ExplodeModifier::Explode(None),
+            RerollModifier::Reroll(Target {
+                cmp: Comparison::Equal,
+                value: 1,
+            }),
+            &limits,
+            DeterministicRng::new(),
+        )
+        .unwrap();
+
+        assert_eq!(roll.vals.len(), 4);
+        assert_eq!(roll.rerolls.len(), 2);
+    }
+
+    #[test]
+    fn test_limits() {
+        let roll = |times, sides| {
+            roll_die(
+                times,
+                sides,
+                FilterModifier::None,
+                ExplodeModifier::None,
+                RerollModifier::None,
+                &Limits::DEFAULT,
+                DeterministicRng::new(),
+            )
+        };
+
+        assert!(roll(Limits::DEFAULT.dice_per_roll, Sides::Number(6)).is_ok());
+        assert!(matches!(
+            roll(999_999_999_999, Sides::Number(6)),
+            Err(RollError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            roll(1, Sides::Number(i64::MAX)),
+            Err(RollError::LimitExceeded(_))
+        ));
+        assert!(matches!(
+            roll(1, Sides::Range(i64::MIN, i64::MAX)),
+            Err(RollError::LimitExceeded(_))
+        ));
+    }
 }
@@ -0,0 +1,167 @@
+use crate::error::RollError;
+use crate::roll::uniform;
+use rand_core::RngCore;
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+/// The faces of a die.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Sides<T> {
+    /// Numbered 1 up to and including the number.
+    Number(T),
+    /// A Fudge die: two `-`, two blank and two `+` faces.
+    Fudge,
+    /// A Fudge die with one `-`, four blank and one `+` face.
+    Fudge1,
+    /// A die with the listed numbers on its faces, like `d{1,1,2,3,5,8}`.
+    Custom(Vec<T>),
+    /// Numbered from the first to the second number, both inclusive, like `d[0..9]`.
+    Range(T, T),
+    /// A die with text symbols on its faces, like `d{hit,miss,crit}`.
+    ///
+    /// A rolled value is the position of the face in the list, starting at 1. Symbols
+    /// don't add anything to the total of a roll.
+    Symbols(Vec<String>),
+}
+
+fn write_list<T: Display>(f: &mut Formatter<'_>, items: &[T]) -> fmt::Result {
+    write!(f, "{{")?;
+    for (index, i) in items.iter().enumerate() {
+        if index != 0 {
+            write!(f, ",")?;
+        }
+        i.fmt(f)?;
+    }
+    write!(f, "}}")
+}
+
+impl<T: Display> Display for Sides<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(v) => v.fmt(f),
+            Self::Fudge => write!(f, "F"),
+            Self::Fudge1 => write!(f, "F.1"),
+            Self::Custom(faces) => write_list(f, faces),
+            Self::Range(lo, hi) => write!(f, "[{}..{}]", lo, hi),
+            Self::Symbols(faces) => write_list(f, faces),
+        }
+    }
+}
+
+impl<T> Sides<T> {
+    pub(crate) fn map<F, U>(self, mut f: F) -> Sides<U>
+    where
+        F: FnMut(T) -> U,
+    {
+        match self {
+            Self::Number(i) => Sides::Number(f(i)),
+            Self::Fudge => Sides::Fudge,
+            Self::Fudge1 => Sides::Fudge1,
+            Self::Custom(faces) => Sides::Custom(faces.into_iter().map(f).collect()),
+            Self::Range(lo, hi) => {
+                let lo = f(lo);
+                Sides::Range(lo, f(hi))
+            }
+            Self::Symbols(faces) => Sides::Symbols(faces),
+        }
+    }
+}
+
+impl<T, E> Sides<Result<T, E>> {
+    pub(crate) fn swap(self) -> Result<Sides<T>, E> {
+        Ok(match self {
+            Sides::Number(i) => Sides::Number(i?),
+            Sides::Fudge => Sides::Fudge,
+            Sides::Fudge1 => Sides::Fudge1,
+            Sides::Custom(faces) => Sides::Custom(faces.into_iter().collect::<Result<_, _>>()?),
+            Sides::Range(lo, hi) => Sides::Range(lo?, hi?),
+            Sides::Symbols(faces) => Sides::Symbols(faces),
+        })
+    }
+}
+
+/// Picks a number from `lo` up to and including `hi`.
+fn uniform_range(rng: &mut impl RngCore, lo: i64, hi: i64) -> i64 {
+    // The number of faces doesn't fit in an i64 for very wide ranges, so
+    // count them as a u64. It wraps to 0 when every i64 is a face.
+    let faces = hi.wrapping_sub(lo) as u64;
+    lo.wrapping_add(uniform(rng, faces.wrapping_add(1)) as i64)
+}
+
+impl Sides<i64> {
+    /// The highest face, which is what a die explodes on by default.
+    pub fn max(&self) -> i64 {
+        match self {
+            Self::Number(n) => *n,
+            Self::Fudge | Self::Fudge1 => 1,
+            Self::Custom(faces) => faces.iter().copied().max().unwrap_or(0),
+            Self::Range(_, hi) => *hi,
+            Self::Symbols(faces) => faces.len() as i64,
+        }
+    }
+
+    /// The number of faces, or `u64::MAX` if there are more.
+    pub fn count(&self) -> u64 {
+        match self {
+            Self::Number(n) => (*n).max(0) as u64,
+            Self::Fudge | Self::Fudge1 => 6,
+            Self::Custom(faces) => faces.len() as u64,
+            Self::Range(lo, hi) => (hi.wrapping_sub(*lo) as u64).saturating_add(1),
+            Self::Symbols(faces) => faces.len() as u64,
+        }
+    }
+
+    /// Checks the die has at least one face: `Sides::Number` has to be at least 1, the
+    /// bounds of `Sides::Range` can't be reversed, and `Sides::Custom` and `Sides::Symbols`
+    /// can't be empty.
+    pub fn check(&self) -> Result<(), RollError> {
+        match self {
+            Self::Number(n) if *n < 1 => Err(RollError::ZeroSides(*n)),
+            Self::Range(lo, hi) if lo > hi => Err(RollError::EmptyRange(*lo, *hi)),
+            Self::Custom(faces) if faces.is_empty() => Err(RollError::ZeroSides(0)),
+            Self::Symbols(faces) if faces.is_empty() => Err(RollError::ZeroSides(0)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rolls a single die, which has to pass `check`.
+    pub fn roll(&self, rng: &mut impl RngCore) -> i64 {
+        match self {
+            Self::Number(n) => uniform_range(rng, 1, *n),
+            Self::Fudge => uniform_range(rng, -1, 1),
+            Self::Fudge1 => match uniform(rng, 6) {
+                0 => -1,
+                5 => 1,
+                _ => 0,
+            },
+            Self::Custom(faces) => faces[uniform(rng, faces.len() as u64) as usize],
+            Self::Range(lo, hi) => uniform_range(rng, *lo, *hi),
+            Self::Symbols(faces) => uniform_range(rng, 1, faces.len() as i64),
+        }
+    }
+
+    /// What a rolled value adds to the total of a roll.
+    pub fn value(&self, v: i64) -> i64 {
+        match self {
+            Self::Symbols(_) => 0,
+            _ => v,
+        }
+    }
+
+    /// Formats a rolled value the way it appears on the die.
+    pub fn display_face(&self, v: i64) -> String {
+        match self {
+            Self::Fudge | Self::Fudge1 => match v {
+                1 => "+".to_string(),
+                -1 => "-".to_string(),
+                _ => " ".to_string(),
+            },
+            Self::Symbols(faces) => (v as usize)
+                .checked_sub(1)
+                .and_then(|i| faces.get(i))
+                .cloned()
+                .unwrap_or_else(|| v.to_string()),
+            Self::Number(_) | Self::Custom(_) | Self::Range(_, _) => v.to_string(),
+        }
+    }
+}
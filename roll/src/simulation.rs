@@ -0,0 +1,386 @@
+use crate::distribution::Distribution;
+use crate::error::RollError;
+use crate::interpreter::Ast;
+use crate::limits::Limits;
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+use std::collections::HashMap;
+use std::thread;
+
+/// Larger simulations are split up into chunks of this many rolls, which are spread over
+/// threads. Every chunk gets its own generator seeded from the one passed in, so the result
+/// doesn't depend on the number of threads.
+const CHUNK: u64 = 10_000;
+
+/// The number of rolls `Method::Auto` simulates when there's no exact distribution.
+pub const DEFAULT_SAMPLES: u64 = 100_000;
+
+/// The z-score of a 95% confidence interval.
+const Z: f64 = 1.96;
+
+/// The distribution of an expression found by rolling it many times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation {
+    distribution: Distribution,
+    samples: u64,
+}
+
+impl Simulation {
+    /// How often every value came up, as a fraction of all rolls.
+    pub fn distribution(&self) -> &Distribution {
+        &self.distribution
+    }
+
+    /// The number of rolls.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// The 95% confidence interval of the mean.
+    pub fn mean_interval(&self) -> (f64, f64) {
+        // The sample variance, which corrects for the mean being estimated as well
+        let n = self.samples as f64;
+        let variance = self.distribution.variance() * n / (n - 1.0).max(1.0);
+        let margin = Z * (variance / n).sqrt();
+        let mean = self.distribution.mean();
+        (mean - margin, mean + margin)
+    }
+
+    /// The 95% confidence interval of the chance of getting `k` or more.
+    pub fn at_least_interval(&self, k: f64) -> (f64, f64) {
+        self.chance_interval(self.distribution.at_least(k))
+    }
+
+    /// The 95% confidence interval of the chance of getting `k` or less.
+    pub fn at_most_interval(&self, k: f64) -> (f64, f64) {
+        self.chance_interval(self.distribution.at_most(k))
+    }
+
+    /// The 95% confidence interval of a chance `p` measured by this simulation.
+    ///
+    /// This is the Wilson score interval, which unlike the usual `p ± z * sqrt(p(1 - p) / n)`
+    /// doesn't collapse to a single point when something (almost) never happened.
+    pub fn chance_interval(&self, p: f64) -> (f64, f64) {
+        let n = self.samples as f64;
+        let z2 = Z * Z;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let margin = Z / (1.0 + z2 / n) * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt();
+        ((center - margin).max(0.0), (center + margin).min(1.0))
+    }
+}
+
+/// How `Ast::analyze` finds the distribution of an expression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Calculates the exact distribution, or simulates `DEFAULT_SAMPLES` rolls when there isn't one.
+    Auto,
+    Exact,
+    /// Simulates the given number of rolls.
+    Simulate(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Analysis {
+    Exact(Distribution),
+    Simulated(Simulation),
+}
+
+impl Analysis {
+    pub fn distribution(&self) -> &Distribution {
+        match self {
+            Self::Exact(d) => d,
+            Self::Simulated(s) => s.distribution(),
+        }
+    }
+}
+
+fn count(counts: &mut HashMap<u64, u64>, other: HashMap<u64, u64>) {
+    for (v, n) in other {
+        *counts.entry(v).or_insert(0) += n;
+    }
+}
+
+impl Ast {
+    /// Rolls this expression `samples` times and counts how often every value comes up.
+    /// Every roll gives an error instead of doing more work than `limits` allow, and so does
+    /// the simulation when all rolls together would roll more than `limits.simulated_dice` dice.
+    pub fn simulate(
+        &self,
+        samples: u64,
+        rng: &mut dyn RngCore,
+        limits: &Limits,
+    ) -> Result<Simulation, RollError> {
+        if samples == 0 {
+            return Err(RollError::NoSamples);
+        }
+
+        // The share of the dice every roll may use
+        let max_dice = limits.simulated_dice / samples;
+        let counts = if samples <= CHUNK {
+            self.sample(samples, max_dice, rng, limits)?
+        } else {
+            let chunks: Vec<(u64, u64)> = (0..samples.div_ceil(CHUNK))
+                .map(|i| (rng.next_u64(), CHUNK.min(samples - i * CHUNK)))
+                .collect();
+            // Threads can't be spawned in the browser
+            let threads = if cfg!(target_arch = "wasm32") {
+                1
+            } else {
+                thread::available_parallelism().map_or(1, |n| n.get())
+            };
+            self.sample_chunks(&chunks, max_dice, threads, limits)?
+        };
+
+        Ok(Simulation {
+            distribution: Distribution::from_outcomes(
+                counts
+                    .into_iter()
+                    .map(|(v, n)| (f64::from_bits(v), n as f64 / samples as f64)),
+            ),
+            samples,
+        })
+    }
+
+    /// Rolls chunks of `(seed, samples)` spread over `threads` threads, or in this thread when
+    /// there's only one.
+    fn sample_chunks(
+        &self,
+        chunks: &[(u64, u64)],
+        max_dice: u64,
+        threads: usize,
+        limits: &Limits,
+    ) -> Result<HashMap<u64, u64>, RollError> {
+        let threads = threads.min(chunks.len());
+        let run = |t: usize| {
+            let mut counts = HashMap::new();
+            for (seed, n) in chunks.iter().skip(t).step_by(threads) {
+                let mut rng = ChaCha20Rng::seed_from_u64(*seed);
+                count(&mut counts, self.sample(*n, max_dice, &mut rng, limits)?);
+            }
+            Ok::<_, RollError>(counts)
+        };
+
+        if threads <= 1 {
+            return run(0);
+        }
+
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..threads)
+                .map(|t| {
+                    let run = &run;
+                    s.spawn(move || run(t))
+                })
+                .collect();
+
+            let mut counts = HashMap::new();
+            for handle in handles {
+                count(&mut counts, handle.join().unwrap()?);
+            }
+            Ok(counts)
+        })
+    }
+
+    /// Rolls this expression `samples` times, where every roll may roll at most `max_dice` dice.
+    fn sample(
+        &self,
+        samples: u64,
+        max_dice: u64,
+        rng: &mut dyn RngCore,
+        limits: &Limits,
+    ) -> Result<HashMap<u64, u64>, RollError> {
+        let mut counts = HashMap::new();
+        for _ in 0..samples {
+            let (v, dice) = self.clone().interp_counting_dice(rng, limits)?;
+            if dice > max_dice {
+                return Err(RollError::LimitExceeded(format!(
+                    "dice to simulate, the limit is {}",
+                    limits.simulated_dice
+                )));
+            }
+            let v = f64::from(v);
+            // Adding 0.0 turns -0.0 into 0.0, so both are counted as the same value
+            *counts.entry((v + 0.0).to_bits()).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+
+    /// Finds the distribution of this expression, either exactly or by simulating it within
+    /// `limits`.
+    pub fn analyze(
+        &self,
+        method: Method,
+        rng: &mut dyn RngCore,
+        limits: &Limits,
+    ) -> Result<Analysis, RollError> {
+        match method {
+            Method::Exact => self.distribution(limits).map(Analysis::Exact),
+            Method::Simulate(samples) => {
+                self.simulate(samples, rng, limits).map(Analysis::Simulated)
+            }
+            // Only simulate when there's no exact answer, not when calculating it is too much work
+            Method::Auto => match self.distribution(limits) {
+                Ok(d) => Ok(Analysis::Exact(d)),
+                Err(RollError::NoExactDistribution(_)) => self
+                    .simulate(DEFAULT_SAMPLES, rng, limits)
+                    .map(Analysis::Simulated),
+                Err(e) => Err(e),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Analysis, Method};
+    use crate::error::RollError;
+    use crate::interpreter::Ast;
+    use crate::limits::Limits;
+    use crate::parser::Parser;
+    use crate::roll::tests::SeededRng;
+
+    fn parse(s: &str) -> Ast {
+        let mut p = Parser::new(s);
+        p.advanced = true;
+        p.parse().unwrap()
+    }
+
+    #[test]
+    fn test_simulate() {
+        let sim = parse("2d6")
+            .simulate(30_000, &mut SeededRng { state: 7 }, &Limits::DEFAULT)
+            .unwrap();
+        let exact = parse("2d6").distribution(&Limits::DEFAULT).unwrap();
+        assert_eq!(sim.samples(), 30_000);
+        assert_eq!(sim.distribution().min(), 2.0);
+        assert_eq!(sim.distribution().max(), 12.0);
+
+        let (lo, hi) = sim.mean_interval();
+        assert!(lo < 7.0 && 7.0 < hi, "{} to {}", lo, hi);
+        assert!((sim.distribution().at_least(10.0) - exact.at_least(10.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_confidence_intervals() {
+        // About 95% of the intervals should contain the real chance
+        const RUNS: u64 = 200;
+        let mut rng = SeededRng { state: 11 };
+        let ast = parse("2d6");
+        let exact = ast.distribution(&Limits::DEFAULT).unwrap();
+
+        let mut hits = 0;
+        for _ in 0..RUNS {
+            let sim = ast.simulate(300, &mut rng, &Limits::DEFAULT).unwrap();
+            let (lo, hi) = sim.at_least_interval(9.0);
+            hits += (lo <= exact.at_least(9.0) && exact.at_least(9.0) <= hi) as u64;
+            let (lo, hi) = sim.at_most_interval(4.0);
+            hits += (lo <= exact.at_most(4.0) && exact.at_most(4.0) <= hi) as u64;
+        }
+
+        let coverage = hits as f64 / (2 * RUNS) as f64;
+        assert!((0.9..0.99).contains(&coverage), "coverage {}", coverage);
+    }
+
+    #[test]
+    fn test_simulate_reproducible() {
+        // Large enough to be split over threads
+        let a = parse("(d8)d(2d4)").simulate(25_000, &mut SeededRng { state: 3 }, &Limits::DEFAULT);
+        let b = parse("(d8)d(2d4)").simulate(25_000, &mut SeededRng { state: 3 }, &Limits::DEFAULT);
+        assert_eq!(a, b);
+
+        let a = parse("d20").simulate(100, &mut SeededRng { state: 3 }, &Limits::DEFAULT);
+        let b = parse("d20").simulate(100, &mut SeededRng { state: 3 }, &Limits::DEFAULT);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_simulate_threads() {
+        // The same chunks give the same counts in this thread as spread over threads
+        let ast = parse("(d8)d(2d4)");
+        let chunks = [(1, 1000), (2, 1000), (3, 500)];
+        let inline = ast
+            .sample_chunks(&chunks, u64::MAX, 1, &Limits::DEFAULT)
+            .unwrap();
+        assert_eq!(
+            inline,
+            ast.sample_chunks(&chunks, u64::MAX, 2, &Limits::DEFAULT)
+                .unwrap()
+        );
+        assert_eq!(
+            inline,
+            ast.sample_chunks(&chunks, u64::MAX, 8, &Limits::DEFAULT)
+                .unwrap()
+        );
+        assert!(parse("2d0")
+            .sample_chunks(&chunks, u64::MAX, 1, &Limits::DEFAULT)
+            .is_err());
+    }
+
+    #[test]
+    fn test_simulate_errors() {
+        let mut rng = SeededRng { state: 0 };
+        assert!(parse("d6").simulate(0, &mut rng, &Limits::DEFAULT).is_err());
+        assert!(parse("2d0")
+            .simulate(20_000, &mut rng, &Limits::DEFAULT)
+            .is_err());
+    }
+
+    #[test]
+    fn test_analyze() {
+        let mut rng = SeededRng { state: 1 };
+        let analyze =
+            |s: &str, method, rng: &mut SeededRng| parse(s).analyze(method, rng, &Limits::DEFAULT);
+
+        assert!(matches!(
+            analyze("3d6", Method::Auto, &mut rng),
+            Ok(Analysis::Exact(_))
+        ));
+        assert!(matches!(
+            analyze("3d6", Method::Simulate(1000), &mut rng),
+            Ok(Analysis::Simulated(_))
+        ));
+
+        // Exploding dice that are filtered don't have an exact distribution
+        assert!(analyze("3d6!kh2", Method::Exact, &mut rng).is_err());
+        match analyze("3d6!kh2", Method::Auto, &mut rng) {
+            Ok(Analysis::Simulated(s)) => assert_eq!(s.samples(), super::DEFAULT_SAMPLES),
+            other => panic!("expected a simulation, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_analyze_limits() {
+        let limits = Limits {
+            dice_per_roll: 10,
+            ..Limits::DEFAULT
+        };
+        let analyze =
+            |s: &str, method| parse(s).analyze(method, &mut SeededRng { state: 1 }, &limits);
+
+        assert!(analyze("10d6!kh2", Method::Auto).is_ok());
+        assert!(analyze("11d6!kh2", Method::Auto).is_err());
+        assert!(analyze("11d6!kh2", Method::Simulate(100)).is_err());
+        assert!(analyze("11d6", Method::Exact).is_err());
+    }
+
+    #[test]
+    fn test_simulation_work() {
+        let mut rng = SeededRng { state: 1 };
+
+        // Too much work to calculate exactly, which shouldn't be simulated instead
+        let ast = parse("10000d1000000kh5000");
+        match ast.analyze(Method::Auto, &mut rng, &Limits::DEFAULT) {
+            Err(RollError::LimitExceeded(_)) => {}
+            other => panic!("expected an error, got {:?}", other),
+        }
+
+        // 100 rolls of 10 dice each
+        let limits = Limits {
+            simulated_dice: 1_000,
+            ..Limits::DEFAULT
+        };
+        assert!(parse("10d6").simulate(100, &mut rng, &limits).is_ok());
+        assert!(parse("11d6").simulate(100, &mut rng, &limits).is_err());
+        assert!(parse("d6!").simulate(20_000, &mut rng, &limits).is_err());
+        assert!(ast.simulate(100_000, &mut rng, &Limits::DEFAULT).is_err());
+    }
+}
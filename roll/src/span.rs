@@ -0,0 +1,67 @@
+use core::fmt;
+
+/// Where a part of an expression is in its source, as the byte offsets `start..end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The part of `source` this span covers.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+
+    /// The number of characters before this span, which is the column it starts at
+    /// when `source` is printed.
+    pub fn column(&self, source: &str) -> usize {
+        source[..self.start].chars().count()
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Ast;
+    use crate::parser::Parser;
+
+    #[test]
+    fn test_spans() {
+        let source = "2d6 + (3 * d4)";
+        let ast = Parser::new(source).parse().unwrap();
+        assert_eq!(ast.span().text(source), source);
+
+        if let Ast::Add(l, r, _) = ast {
+            assert_eq!(l.span().text(source), "2d6");
+            assert_eq!(r.span().text(source), "3 * d4");
+        } else {
+            panic!("expected a sum");
+        }
+    }
+
+    #[test]
+    fn test_multibyte() {
+        let source = "d{é, ü} + d6";
+        let mut rolls = Vec::new();
+        Parser::new(source)
+            .parse()
+            .unwrap()
+            .interp(&mut rolls)
+            .unwrap();
+
+        let spans: Vec<&str> = rolls.iter().map(|(s, _)| s.text(source)).collect();
+        assert_eq!(spans, ["d{é, ü}", "d6"]);
+        assert_eq!(rolls[1].0.start, 12);
+        assert_eq!(rolls[1].0.column(source), 10);
+    }
+}
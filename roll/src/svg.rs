@@ -0,0 +1,231 @@
+use crate::distribution::Distribution;
+use std::fmt::Write;
+use std::str::FromStr;
+
+const WIDTH: f64 = 640.0;
+const HEIGHT: f64 = 400.0;
+const LEFT: f64 = 60.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 40.0;
+const BOTTOM: f64 = 40.0;
+
+/// The most labels shown below the x axis, more would overlap.
+const MAX_LABELS: usize = 20;
+
+const COLORS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chart {
+    /// The bars of every distribution are put next to each other.
+    Bar,
+    Line,
+}
+
+impl FromStr for Chart {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bar" => Ok(Chart::Bar),
+            "line" => Ok(Chart::Line),
+            _ => Err("expected bar or line"),
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Draws the chance of every outcome of one or more distributions, with the name of every
+/// distribution in the legend. Returns a complete SVG document.
+pub fn svg_chart(series: &[(&str, &Distribution)], chart: Chart) -> String {
+    // Every value any of the distributions can have gets a place on the x axis
+    let mut values: Vec<f64> = series
+        .iter()
+        .flat_map(|(_, d)| d.outcomes().iter().map(|(v, _)| *v))
+        .collect();
+    values.sort_by(f64::total_cmp);
+    values.dedup();
+
+    let highest = series
+        .iter()
+        .flat_map(|(_, d)| d.outcomes().iter().map(|(_, p)| *p))
+        .fold(0.0, f64::max);
+    // Round the top of the y axis up to a whole percentage, so the grid lines are too
+    let top = ((highest * 100.0 / 5.0).ceil() * 5.0 / 100.0).max(0.05);
+
+    let plot_width = WIDTH - LEFT - RIGHT;
+    let plot_height = HEIGHT - TOP - BOTTOM;
+    let slot = plot_width / values.len().max(1) as f64;
+    let x = |index: usize| LEFT + slot * (index as f64 + 0.5);
+    let y = |p: f64| TOP + plot_height * (1.0 - p / top);
+
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}" font-family="sans-serif" font-size="12">"#,
+        WIDTH, HEIGHT, WIDTH, HEIGHT
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r#"<rect width="{}" height="{}" fill="white"/>"#,
+        WIDTH, HEIGHT
+    )
+    .unwrap();
+
+    // Horizontal grid lines with the chance on the y axis
+    for i in 0..=5 {
+        let p = top * i as f64 / 5.0;
+        writeln!(
+            svg,
+            r##"<line x1="{}" y1="{:.1}" x2="{}" y2="{:.1}" stroke="#ddd"/>"##,
+            LEFT,
+            y(p),
+            WIDTH - RIGHT,
+            y(p)
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{:.1}" text-anchor="end" dominant-baseline="middle">{}%</text>"#,
+            LEFT - 6.0,
+            y(p),
+            (p * 1000.0).round() / 10.0
+        )
+        .unwrap();
+    }
+
+    // The values on the x axis, skipping some when there are too many to fit
+    let step = values.len().div_ceil(MAX_LABELS).max(1);
+    for (index, v) in values.iter().enumerate().step_by(step) {
+        writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{}" text-anchor="middle">{}</text>"#,
+            x(index),
+            HEIGHT - BOTTOM + 16.0,
+            v
+        )
+        .unwrap();
+    }
+
+    for (n, (_, dist)) in series.iter().enumerate() {
+        let color = COLORS[n % COLORS.len()];
+        let chances = values.iter().map(|v| dist.probability(*v));
+
+        match chart {
+            Chart::Bar => {
+                let width = slot * 0.8 / series.len() as f64;
+                for (index, p) in chances.enumerate() {
+                    if p == 0.0 {
+                        continue;
+                    }
+                    let left = x(index) - slot * 0.4 + width * n as f64;
+                    writeln!(
+                        svg,
+                        r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" fill="{}"/>"#,
+                        left,
+                        y(p),
+                        width,
+                        y(0.0) - y(p),
+                        color
+                    )
+                    .unwrap();
+                }
+            }
+            Chart::Line => {
+                let points: Vec<String> = chances
+                    .enumerate()
+                    .map(|(index, p)| format!("{:.1},{:.1}", x(index), y(p)))
+                    .collect();
+                writeln!(
+                    svg,
+                    r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+                    points.join(" "),
+                    color
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    writeln!(
+        svg,
+        r##"<line x1="{}" y1="{:.1}" x2="{}" y2="{:.1}" stroke="#333"/>"##,
+        LEFT,
+        y(0.0),
+        WIDTH - RIGHT,
+        y(0.0)
+    )
+    .unwrap();
+
+    // The legend goes above the chart, from left to right
+    let mut left = LEFT;
+    for (n, (name, _)) in series.iter().enumerate() {
+        writeln!(
+            svg,
+            r#"<rect x="{:.1}" y="{}" width="12" height="12" fill="{}"/>"#,
+            left,
+            TOP / 2.0 - 6.0,
+            COLORS[n % COLORS.len()]
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r#"<text x="{:.1}" y="{}" dominant-baseline="middle">{}</text>"#,
+            left + 16.0,
+            TOP / 2.0,
+            escape(name)
+        )
+        .unwrap();
+        // Roughly the width of the text, there's no way to measure it here
+        left += 16.0 + name.chars().count() as f64 * 7.0 + 20.0;
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{svg_chart, Chart};
+    use crate::parser::Parser;
+
+    fn dist(s: &str) -> crate::distribution::Distribution {
+        Parser::new(s)
+            .parse()
+            .unwrap()
+            .distribution(&crate::limits::Limits::DEFAULT)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bar_chart() {
+        let svg = svg_chart(&[("d6", &dist("d6"))], Chart::Bar);
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+
+        // The background, six bars and the legend
+        assert_eq!(svg.matches("<rect").count(), 8);
+        assert_eq!(svg.matches("#4e79a7").count(), 7);
+    }
+
+    #[test]
+    fn test_overlay() {
+        let svg = svg_chart(
+            &[("2d6", &dist("2d6")), ("3d10>8", &dist("3d10>8"))],
+            Chart::Line,
+        );
+        assert_eq!(svg.matches("<polyline").count(), 2);
+        // Every line covers the values of both distributions, 0 through 12
+        let line = svg.lines().find(|l| l.starts_with("<polyline")).unwrap();
+        assert_eq!(line.matches(',').count(), 13);
+        assert!(svg.contains(">3d10&gt;8</text>"));
+    }
+}
@@ -1,10 +1,22 @@
-use roll_rs::{roll_inline, Parser};
+use roll_rs::rand_core::OsRng;
+use roll_rs::{
+    analyze_with_limits, roll_inline_with_limits, svg_chart, Chart, Limits, Method, Outcome,
+    Parser, RollError,
+};
 use serde::Deserialize;
 use serde::Serialize;
 use wasm_bindgen::prelude::*;
 
 // to build:  wasm-pack build --target web
 
+/// Rolls run in the browser's main thread, so allow less work than the library does.
+const LIMITS: Limits = Limits {
+    dice_per_roll: 1_000,
+    total_dice: 10_000,
+    simulated_dice: 10_000_000,
+    ..Limits::DEFAULT
+};
+
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
@@ -19,14 +31,43 @@ pub enum ObjType {
     JsRolls,
 }
 
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JsOutcome {
+    Success,
+    Failure,
+    Neither,
+}
+
+impl From<Outcome> for JsOutcome {
+    fn from(o: Outcome) -> Self {
+        match o {
+            Outcome::Success => JsOutcome::Success,
+            Outcome::Failure => JsOutcome::Failure,
+            Outcome::Neither => JsOutcome::Neither,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct JsRoll {
     #[serde(rename = "type")]
     pub obj_type: ObjType,
-    pub vals: Vec<u64>,
+    pub vals: Vec<i64>,
+    /// `vals` as they appear on the die, for example `+` and `-` for Fudge dice.
+    pub faces: Vec<String>,
     pub total: i64,
-    pub sides: u64,
-    pub dpos: u64,
+    pub sides: String,
+    /// The byte offsets of the dice in the expression, from `start` up to `end`.
+    pub start: usize,
+    pub end: usize,
+    pub rerolls: Vec<(i64, i64)>,
+    /// `rerolls` as they appear on the die.
+    pub reroll_faces: Vec<(String, String)>,
+    /// For dice with symbols on their faces, how many times each symbol came up. Symbols
+    /// don't add to `total`.
+    pub symbols: Vec<(String, usize)>,
+    /// Only filled for dice pools, one entry per value in `vals`.
+    pub outcomes: Vec<JsOutcome>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -37,10 +78,14 @@ pub struct JsRolls {
     pub total: f64,
 }
 
+fn js_error(e: RollError) -> JsValue {
+    JsValue::from(e.to_string())
+}
+
 #[wasm_bindgen]
 pub fn roll_dice_short(s: &str, advanced: bool) -> Result<String, JsValue> {
-    roll_inline(s, advanced)
-        .map_err(|s| JsValue::from("\n".to_string() + &s))
+    roll_inline_with_limits(s, advanced, &mut OsRng, &LIMITS)
+        .map_err(|e| JsValue::from(format!("\n{}", e)))
         .map(|result| result.string_result)
 }
 
@@ -49,19 +94,31 @@ pub fn roll_dice(s: &str, advanced: bool) -> Result<JsValue, JsValue> {
     let mut p = Parser::new(s);
     p.advanced = advanced;
 
-    let ast = p.parse().map_err(|e| JsValue::from(e.to_string()))?;
+    let ast = p.parse().map_err(js_error)?;
 
     let mut rolls = Vec::new();
-    let res = ast.interp(&mut rolls)?;
+    let res = ast
+        .interp_with_limits(&mut rolls, &mut OsRng, &LIMITS)
+        .map_err(js_error)?;
 
     let rolls: Vec<JsRoll> = rolls
         .into_iter()
-        .map(|(dpos, r)| JsRoll {
+        .map(|(span, r)| JsRoll {
             obj_type: ObjType::JsRoll,
+            faces: r.vals.iter().map(|v| r.sides.display_face(*v)).collect(),
+            reroll_faces: r
+                .rerolls
+                .iter()
+                .map(|(from, to)| (r.sides.display_face(*from), r.sides.display_face(*to)))
+                .collect(),
+            symbols: r.symbol_counts(),
             vals: r.vals,
             total: r.total,
-            sides: r.sides.get(),
-            dpos,
+            sides: r.sides.to_string(),
+            start: span.start,
+            end: span.end,
+            rerolls: r.rerolls,
+            outcomes: r.outcomes.into_iter().map(JsOutcome::from).collect(),
         })
         .collect();
 
@@ -74,6 +131,34 @@ pub fn roll_dice(s: &str, advanced: bool) -> Result<JsValue, JsValue> {
     Ok(serde_wasm_bindgen::to_value(&res)?)
 }
 
+/// The distribution of a dice expression as JSON, see `Analysis::to_json`. It's exact when
+/// possible, otherwise it's simulated.
+#[wasm_bindgen]
+pub fn analyze_dice(s: &str, advanced: bool) -> Result<String, JsValue> {
+    analyze_with_limits(s, advanced, Method::Auto, &mut OsRng, &LIMITS)
+        .map_err(js_error)
+        .map(|analysis| analysis.to_json())
+}
+
+/// An SVG chart of the distributions of one or more dice expressions, drawn on top of
+/// each other. `chart` is either "bar" or "line".
+#[wasm_bindgen]
+pub fn chart_dice(codes: Vec<String>, advanced: bool, chart: &str) -> Result<String, JsValue> {
+    let chart: Chart = chart.parse().map_err(JsValue::from)?;
+    let analyses = codes
+        .iter()
+        .map(|s| analyze_with_limits(s, advanced, Method::Auto, &mut OsRng, &LIMITS))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(js_error)?;
+
+    let series: Vec<_> = codes
+        .iter()
+        .zip(&analyses)
+        .map(|(s, a)| (s.as_str(), a.distribution()))
+        .collect();
+    Ok(svg_chart(&series, chart))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -92,13 +177,58 @@ mod test {
             assert_eq!(ObjType::JsRoll, roll.obj_type);
         }
 
-        assert_eq!(8, de.rolls[0].sides);
+        assert_eq!("8", de.rolls[0].sides);
         assert_eq!(2, de.rolls[0].vals.len());
 
-        assert_eq!(100, de.rolls[1].sides);
+        assert_eq!("100", de.rolls[1].sides);
         assert_eq!(1, de.rolls[1].vals.len());
     }
 
+    #[wasm_bindgen_test]
+    fn smoke_roll_pool() {
+        let res = roll_dice("10d10>=7f1", false).unwrap();
+        let de: JsRolls = serde_wasm_bindgen::from_value(res).unwrap();
+
+        assert_eq!(10, de.rolls[0].outcomes.len());
+    }
+
+    #[wasm_bindgen_test]
+    fn smoke_roll_fudge() {
+        let res = roll_dice("4dF", false).unwrap();
+        let de: JsRolls = serde_wasm_bindgen::from_value(res).unwrap();
+
+        assert_eq!("F", de.rolls[0].sides);
+        assert_eq!(4, de.rolls[0].faces.len());
+    }
+
+    #[wasm_bindgen_test]
+    fn smoke_roll_symbols() {
+        let res = roll_dice("3d{hit,miss}ro=2", false).unwrap();
+        let de: JsRolls = serde_wasm_bindgen::from_value(res).unwrap();
+        let roll = &de.rolls[0];
+
+        assert_eq!("{hit,miss}", roll.sides);
+        assert_eq!(0, roll.total);
+        assert!(roll.faces.iter().all(|f| f == "hit" || f == "miss"));
+        assert_eq!(roll.rerolls.len(), roll.reroll_faces.len());
+        assert!(roll.reroll_faces.iter().all(|(from, _)| from == "miss"));
+        assert_eq!(3, roll.symbols.iter().map(|(_, n)| n).sum::<usize>());
+    }
+
+    #[wasm_bindgen_test]
+    fn smoke_analyze() {
+        let res = analyze_dice("2d6", false).unwrap();
+        assert!(res.starts_with(r#"{"exact":true,"mean":7,"#));
+        assert!(res.contains(r#"{"outcome":12,"#));
+    }
+
+    #[wasm_bindgen_test]
+    fn smoke_chart() {
+        let res = chart_dice(vec!["2d6".to_string(), "d12".to_string()], false, "line").unwrap();
+        assert!(res.starts_with("<svg "));
+        assert_eq!(2, res.matches("<polyline").count());
+    }
+
     #[wasm_bindgen_test]
     fn smoke_roll_short() {
         let res = roll_dice_short("4d8", false).unwrap();